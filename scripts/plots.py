@@ -7,6 +7,10 @@ if len(sys.argv) != 2:
 
 data = np.load(sys.argv[1])
 
+# Follow each particle by its id (column 2) rather than its place in the array
+for snapshot in data:
+    snapshot[:] = snapshot[np.argsort(snapshot[:, 2])]
+
 fig, ax = plt.subplots(2)
 
 # Z plot
@@ -1,37 +1,364 @@
 use crate::elements::EleType;
-use ndarray::{arr2, s, Array2, Axis};
-use std::io::Write;
+use ndarray::{arr2, s, Array1, Array2, ArrayView1, ArrayView2, Axis};
+use std::io::{self, Write};
+use std::str::FromStr;
 
-use crate::elements::Element;
+use crate::elements::{
+    synchrotron_radiation_loss, synchrotron_radiation_spread, DeflectorDetails, Element,
+};
+use crate::random::Rng;
 pub const MASS: f64 = 510998.9499961642f64;
 pub const C: f64 = 299792458f64;
+/// Classical electron radius (m)
+pub const ELECTRON_RADIUS: f64 = 2.8179403262e-15;
+/// Reduced Compton wavelength of the electron (m)
+pub const COMPTON_WAVELENGTH: f64 = 3.8615926796e-13;
+/// Number of bins used for the current profile in the REPL
+pub const CURRENT_PROFILE_BINS: usize = 100;
+/// Half-width (in bins) of the moving average used to smooth the current profile in the REPL
+pub const CURRENT_PROFILE_SMOOTHING: usize = 1;
 
-// TODO(#1): The beam should (?) be sorted by the z coord
+/// How the path length through drifts and dipoles is calculated
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TrackingOrder {
+    #[default]
+    Linear,
+    Second,
+    /// Expansion up to U5666
+    Third,
+    /// Time of flight from each particle's own velocity and bending radius
+    Exact,
+}
+
+impl FromStr for TrackingOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(TrackingOrder::Linear),
+            "2" => Ok(TrackingOrder::Second),
+            "3" => Ok(TrackingOrder::Third),
+            "exact" => Ok(TrackingOrder::Exact),
+            other => Err(format!(
+                "Unknown tracking order '{other}'. Expected '1', '2', '3' or 'exact'"
+            )),
+        }
+    }
+}
+
+/// Macroparticles in the longitudinal phase space. Tracking keeps the rows sorted by z, so the
+/// row of a particle changes; `ids` follows each particle.
 #[derive(Clone)]
 pub struct Beam {
     pub pos: Array2<f64>,
+    /// Total bunch charge (C)
+    pub charge: f64,
+    /// Relative weight of each macroparticle. The total charge is shared in proportion to these.
+    pub weights: Array1<f64>,
+    /// Where each macroparticle was lost, or `None` while it is still in the beam. Lost
+    /// particles keep their charge, but are no longer tracked or counted in the statistics.
+    pub lost: Vec<Option<ParticleLoss>>,
+    /// Number of elements the beam has been tracked through, which is the index of the next
+    pub elements_tracked: usize,
+    /// Identifier of each macroparticle, which is its row in the beam as first made
+    pub ids: Array1<usize>,
+}
+
+/// The element in which a macroparticle was lost
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleLoss {
+    /// Index of the element in the line
+    pub element: usize,
+    pub name: String,
 }
 
 impl Beam {
     pub fn new(pos: Array2<f64>) -> Self {
-        Self { pos }
+        let num_particles = pos.len_of(Axis(0));
+        Self {
+            pos,
+            charge: 0f64,
+            weights: Array1::ones(num_particles),
+            lost: vec![None; num_particles],
+            elements_tracked: 0,
+            ids: Array1::from_iter(0..num_particles),
+        }
+    }
+
+    pub fn is_sorted_by_z(&self) -> bool {
+        let z = self.pos.column(0);
+        z.iter()
+            .zip(z.iter().skip(1))
+            .all(|(a, b)| a.total_cmp(b).is_le())
+    }
+
+    /// Reorders the particles by z, tail first. Particles with the same z keep their order.
+    pub fn sort_by_z(&mut self) {
+        let z = self.pos.column(0);
+        let mut rows: Vec<usize> = (0..z.len()).collect();
+        rows.sort_by(|&a, &b| z[a].total_cmp(&z[b]));
+        self.reorder(&rows);
+    }
+
+    /// Row of the particle with `id`
+    pub fn row_of(&self, id: usize) -> Option<usize> {
+        self.ids.iter().position(|&other| other == id)
+    }
+
+    /// Rows of the particles in order of their ids
+    pub fn rows_by_id(&self) -> Vec<usize> {
+        let mut rows: Vec<usize> = (0..self.ids.len()).collect();
+        rows.sort_by_key(|&row| self.ids[row]);
+        rows
+    }
+
+    /// The positions of the particles in order of their ids
+    pub fn pos_by_id(&self) -> Array2<f64> {
+        self.pos.select(Axis(0), &self.rows_by_id())
+    }
+
+    /// z, delta and id of each particle, in order of their ids. The coordinates of lost
+    /// particles are NaN.
+    pub fn phase_space_by_id(&self) -> Array2<f64> {
+        let rows = self.rows_by_id();
+        let mut phase_space = Array2::zeros((rows.len(), 3));
+        for (mut particle, &row) in phase_space.outer_iter_mut().zip(&rows) {
+            let (z, delta) = if self.is_alive(row) {
+                (self.pos[[row, 0]], self.pos[[row, 1]])
+            } else {
+                (f64::NAN, f64::NAN)
+            };
+            particle[0] = z;
+            particle[1] = delta;
+            particle[2] = self.ids[row] as f64;
+        }
+        phase_space
+    }
+
+    fn reorder(&mut self, rows: &[usize]) {
+        self.pos = self.pos.select(Axis(0), rows);
+        self.weights = self.weights.select(Axis(0), rows);
+        self.ids = self.ids.select(Axis(0), rows);
+        self.lost = rows.iter().map(|&row| self.lost[row].clone()).collect();
+    }
+
+    pub fn is_alive(&self, particle: usize) -> bool {
+        self.lost[particle].is_none()
+    }
+
+    fn has_losses(&self) -> bool {
+        self.lost.iter().any(Option::is_some)
+    }
+
+    /// Weights of the particles still in the beam, with zero for those that have been lost
+    pub fn live_weights(&self) -> Array1<f64> {
+        self.weights_after(self.elements_tracked)
+    }
+
+    /// Weights of the particles that were still in the beam after the first `elements` elements
+    pub fn weights_after(&self, elements: usize) -> Array1<f64> {
+        self.weights
+            .iter()
+            .zip(&self.lost)
+            .map(|(&weight, loss)| match loss {
+                Some(loss) if loss.element < elements => 0f64,
+                _ => weight,
+            })
+            .collect()
+    }
+
+    /// Fraction of the charge still in the beam
+    pub fn transmission(&self) -> f64 {
+        let total_weight = self.weights.sum();
+        if total_weight == 0f64 {
+            return 0f64;
+        }
+        self.live_weights().sum() / total_weight
+    }
+
+    /// The particles still in the beam, each keeping its own charge
+    pub fn live_particles(&self) -> Beam {
+        let rows: Vec<usize> = (0..self.lost.len()).filter(|&i| self.is_alive(i)).collect();
+        let mut live = Beam::new(self.pos.select(Axis(0), &rows));
+        live.weights = self.weights.select(Axis(0), &rows);
+        live.ids = self.ids.select(Axis(0), &rows);
+        live.charge = self.charge * self.transmission();
+        live.elements_tracked = self.elements_tracked;
+        live
+    }
+
+    /// Charge (C) carried by each macroparticle
+    pub fn particle_charges(&self) -> Array1<f64> {
+        let total_weight = self.weights.sum();
+        if total_weight == 0f64 {
+            return Array1::zeros(self.weights.len());
+        }
+        &self.weights * (self.charge / total_weight)
+    }
+
+    /// Histogram of the beam current I(z) in `n_bins` bins, smoothed with a moving average of
+    /// half-width `smoothing` bins (0 for no smoothing). Assumes the particles are ultra-relativistic.
+    pub fn current_profile(&self, n_bins: usize, smoothing: usize) -> CurrentProfile {
+        if self.has_losses() {
+            return self.live_particles().current_profile(n_bins, smoothing);
+        }
+        let z = self.pos.column(0);
+        if z.is_empty() || n_bins == 0 {
+            return CurrentProfile {
+                z: vec![],
+                current: vec![],
+            };
+        }
+        let z_min = z.fold(f64::INFINITY, |a, &b| a.min(b));
+        let z_max = z.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let bin_width = if z_max > z_min {
+            (z_max - z_min) / n_bins as f64
+        } else {
+            f64::EPSILON.max(z_min.abs() * f64::EPSILON)
+        };
+
+        let mut charge = vec![0f64; n_bins];
+        for (&z_val, q) in z.iter().zip(self.particle_charges()) {
+            let bin = (((z_val - z_min) / bin_width) as usize).min(n_bins - 1);
+            charge[bin] += q;
+        }
+
+        let current: Vec<f64> = (0..n_bins)
+            .map(|bin| {
+                let low = bin.saturating_sub(smoothing);
+                let high = (bin + smoothing).min(n_bins - 1);
+                let summed: f64 = charge[low..=high].iter().sum();
+                summed / (high - low + 1) as f64 * C / bin_width
+            })
+            .collect();
+
+        CurrentProfile {
+            z: (0..n_bins)
+                .map(|bin| z_min + (bin as f64 + 0.5) * bin_width)
+                .collect(),
+            current,
+        }
+    }
+
+    /// Statistics of the particles still in the beam. The coordinates of lost particles may not
+    /// be numbers, so they are left out rather than given no weight.
+    pub fn stats(&self) -> BeamStats {
+        if self.has_losses() {
+            return self.live_particles().stats();
+        }
+        BeamStats::new(self.pos.view(), self.weights.view())
     }
 
     pub fn track(&mut self, ele: &Element) {
-        match ele.ele_type {
-            EleType::Drift | EleType::Dipole => {
-                let r56 = match ele.params.get("r56") {
-                    Some(val) => *val,
-                    None => 0f64,
-                };
-                let t_matrix = arr2(&[[1f64, 0f64], [r56, 1f64]]);
-                self.pos = self.pos.dot(&t_matrix);
+        self.track_with_order(ele, TrackingOrder::default());
+    }
+
+    pub fn track_with_order(&mut self, ele: &Element, order: TrackingOrder) {
+        if !self.is_sorted_by_z() {
+            self.sort_by_z();
+        }
+        if self.has_losses() {
+            // Only the particles still in the beam are tracked, and they alone act collectively
+            let rows: Vec<usize> = (0..self.lost.len()).filter(|&i| self.is_alive(i)).collect();
+            let mut live = self.live_particles();
+            live.track_with_order(ele, order);
+            for (live_row, &row) in rows.iter().enumerate() {
+                self.pos.row_mut(row).assign(&live.pos.row(live_row));
+                self.weights[row] = live.weights[live_row];
+                self.ids[row] = live.ids[live_row];
+                self.lost[row] = live.lost[live_row].take();
+            }
+            self.elements_tracked = live.elements_tracked;
+        } else {
+            self.track_particles(ele, order);
+        }
+        if ele.can_reorder() && !self.is_sorted_by_z() {
+            self.sort_by_z();
+        }
+    }
+
+    fn track_particles(&mut self, ele: &Element, order: TrackingOrder) {
+        match &ele.ele_type {
+            EleType::Drift(details) => match &details.lsc {
+                Some(lsc) if self.charge != 0f64 => {
+                    // Half of the space-charge kick is taken from the bunch at each end of the drift
+                    let half_length = ele.length / 2f64;
+                    let kicks = lsc.energy_kicks(
+                        self.pos.column(0),
+                        self.weights.view(),
+                        self.charge,
+                        ele.gamma,
+                        half_length,
+                    );
+                    self.apply_energy_kicks(&kicks, ele.gamma);
+                    self.track_path_length(ele, order);
+                    let kicks = lsc.energy_kicks(
+                        self.pos.column(0),
+                        self.weights.view(),
+                        self.charge,
+                        ele.gamma,
+                        half_length,
+                    );
+                    self.apply_energy_kicks(&kicks, ele.gamma);
+                }
+                _ => self.track_path_length(ele, order),
+            },
+            EleType::Dipole(details) => {
+                let angle = ele.params.get("angle").copied().unwrap_or(0f64);
+                match &details.csr {
+                    Some(csr) if angle != 0f64 && self.charge != 0f64 => {
+                        // Half of the CSR kick is taken from the bunch at each end of the dipole
+                        let radius = ele.length / angle;
+                        let half_length = ele.length / 2f64;
+                        let kicks = csr.energy_kicks(
+                            self.pos.column(0),
+                            self.weights.view(),
+                            self.charge,
+                            radius,
+                            half_length,
+                        );
+                        self.apply_energy_kicks(&kicks, ele.gamma);
+                        self.track_path_length(ele, order);
+                        let kicks = csr.energy_kicks(
+                            self.pos.column(0),
+                            self.weights.view(),
+                            self.charge,
+                            radius,
+                            half_length,
+                        );
+                        self.apply_energy_kicks(&kicks, ele.gamma);
+                    }
+                    _ => self.track_path_length(ele, order),
+                }
+                if details.synch_rad {
+                    self.radiate(ele, angle, details.isr_seed);
+                }
+            }
+            EleType::Deflector(_) => self.track_path_length(ele, order),
+            EleType::Wiggler(details) => {
+                if let Some(heater) = &details.laser_heater {
+                    let kicks = heater.energy_kicks(self.pos.column(0));
+                    self.apply_energy_kicks(&kicks, ele.gamma);
+                }
+                self.track_path_length(ele, order);
+            }
+            EleType::Collimator(details) => {
+                for (particle, loss) in self.pos.outer_iter().zip(self.lost.iter_mut()) {
+                    if !details.accepts(particle[1]) {
+                        *loss = Some(ParticleLoss {
+                            element: self.elements_tracked,
+                            name: ele.name.clone(),
+                        });
+                    }
+                }
+                self.track_path_length(ele, order);
             }
             EleType::AccCav(details) => {
                 let gamma0_i = ele.gamma;
                 let beta0_i = gamma_2_beta(gamma0_i);
-                let gamma0_f =
-                    ke_2_gamma(gamma_2_ke(gamma0_i) + details.voltage * details.phase.cos());
+                let gamma0_f = ke_2_gamma(gamma_2_ke(gamma0_i) + details.design_energy_gain());
+                let crest_phase = details.crest_phase();
+                let voltage = details.total_voltage();
 
                 let r56_drift = (details.length / 2f64) / (beta0_i.powi(2) * gamma0_i.powi(2));
                 let drift_matrix = arr2(&[[1f64, 0f64], [r56_drift, 1f64]]);
@@ -39,30 +366,410 @@ impl Beam {
                 self.pos = self.pos.dot(&drift_matrix);
 
                 for mut particle in self.pos.outer_iter_mut() {
-                    let actual_phase = details.phase - particle[0] * details.wavenumber;
-                    let new_ke =
-                        delta_2_ke(particle[1], gamma0_i) + (details.voltage * actual_phase.cos());
+                    let actual_phase = crest_phase - particle[0] * details.wavenumber;
+                    let new_ke = delta_2_ke(particle[1], gamma0_i) + (voltage * actual_phase.cos());
                     let new_gamma = ke_2_gamma(new_ke);
 
                     particle[1] = gamma_2_delta(new_gamma, gamma0_f);
                 }
 
+                if let Some(wake) = details.wake.as_ref().filter(|_| self.charge != 0f64) {
+                    let kicks = wake.energy_kicks(
+                        self.pos.column(0),
+                        self.weights.view(),
+                        self.charge,
+                        details.length,
+                    );
+                    self.apply_energy_kicks(&kicks, gamma0_f);
+                }
+
                 self.pos = self.pos.dot(&drift_matrix);
             }
         }
+        self.lose_unphysical(ele);
+        self.elements_tracked += 1;
+    }
+
+    /// Marks as lost the particles that have been stopped, that bend too far to leave a dipole,
+    /// or whose coordinates are no longer numbers
+    fn lose_unphysical(&mut self, ele: &Element) {
+        let gamma0 = ele.exit_gamma();
+        let angle = match &ele.ele_type {
+            EleType::Dipole(_) => ele.params.get("angle").copied().unwrap_or(0f64),
+            _ => 0f64,
+        };
+        let beta_gamma0 = gamma_2_beta(gamma0) * gamma0;
+        for (particle, loss) in self.pos.outer_iter().zip(self.lost.iter_mut()) {
+            if loss.is_some() {
+                continue;
+            }
+            let gamma = delta_2_gamma(particle[1], gamma0);
+            // Written so that NaN counts as unphysical
+            let moving = gamma > 1f64;
+            let leaves_dipole = angle == 0f64 || {
+                let p_ratio = gamma_2_beta(gamma) * gamma / beta_gamma0;
+                ((p_ratio - 1f64) * angle.sin() / p_ratio).abs() <= 1f64
+            };
+            if !(moving && leaves_dipole && particle[0].is_finite()) {
+                *loss = Some(ParticleLoss {
+                    element: self.elements_tracked,
+                    name: ele.name.clone(),
+                });
+            }
+        }
+    }
+
+    fn track_path_length(&mut self, ele: &Element, order: TrackingOrder) {
+        if order == TrackingOrder::Exact {
+            let angle = ele.params.get("angle").copied().unwrap_or(0f64);
+            let wiggler_k = match &ele.ele_type {
+                EleType::Wiggler(details) => details.k,
+                _ => 0f64,
+            };
+            for mut particle in self.pos.outer_iter_mut() {
+                particle[0] += exact_path_delta_z(particle[1], ele.gamma, ele.length, angle);
+                if wiggler_k != 0f64 {
+                    // The mean longitudinal velocity is lowered by K^2 / (4 gamma^2)
+                    let gamma = delta_2_gamma(particle[1], ele.gamma);
+                    particle[0] += ele.length * wiggler_k.powi(2) / 4f64
+                        * (1f64 / ele.gamma.powi(2) - 1f64 / gamma.powi(2));
+                }
+            }
+            return;
+        }
+        let param = |key: &str| ele.params.get(key).copied().unwrap_or(0f64);
+        let r56 = param("r56");
+        let t566 = if order == TrackingOrder::Linear {
+            0f64
+        } else {
+            param("t566")
+        };
+        let u5666 = if order == TrackingOrder::Third {
+            param("u5666")
+        } else {
+            0f64
+        };
+        for mut particle in self.pos.outer_iter_mut() {
+            let delta = particle[1];
+            particle[0] += delta * (r56 + delta * (t566 + delta * u5666));
+        }
+    }
+
+    /// The image on a screen `r34` (m/rad) after the `deflector`, for a beam entering it with
+    /// the reference `gamma0`
+    pub fn streaked_image(
+        &self,
+        deflector: &DeflectorDetails,
+        gamma0: f64,
+        r34: f64,
+    ) -> StreakedImage {
+        let screen_pos = self
+            .pos
+            .outer_iter()
+            .map(|particle| {
+                let gamma = delta_2_gamma(particle[1], gamma0);
+                deflector.screen_position(particle[0], gamma, r34)
+            })
+            .collect();
+        StreakedImage {
+            screen_pos,
+            ids: self.ids.clone(),
+            weights: self.weights.clone(),
+            charges: self.particle_charges(),
+            calibration: deflector.streak_calibration(gamma0, r34),
+        }
+    }
+
+    /// Removes the energy lost to synchrotron radiation in a dipole, with a random spread if
+    /// `isr_seed` is given. Afterwards, delta is relative to the reference after its own loss.
+    fn radiate(&mut self, ele: &Element, angle: f64, isr_seed: Option<u64>) {
+        let gamma0_f = ele.gamma - ele.design_energy_loss() / MASS;
+        let mut rng = isr_seed.map(Rng::new);
+        for mut particle in self.pos.outer_iter_mut() {
+            let gamma = delta_2_gamma(particle[1], ele.gamma);
+            let mut loss = synchrotron_radiation_loss(gamma, ele.length, angle);
+            if let Some(rng) = rng.as_mut() {
+                loss += synchrotron_radiation_spread(gamma, ele.length, angle) * rng.gaussian();
+            }
+            particle[1] = gamma_2_delta(gamma - loss / MASS, gamma0_f);
+        }
+    }
+
+    /// Measures the delta of each particle from a reference with `new_gamma0` instead of `gamma0`
+    pub fn change_reference(&mut self, gamma0: f64, new_gamma0: f64) {
+        for mut particle in self.pos.outer_iter_mut() {
+            particle[1] = gamma_2_delta(delta_2_gamma(particle[1], gamma0), new_gamma0);
+        }
+    }
+
+    /// Adds `kicks` (eV) to the energy of each particle, where the reference has `gamma0`
+    fn apply_energy_kicks(&mut self, kicks: &[f64], gamma0: f64) {
+        for (mut particle, kick) in self.pos.outer_iter_mut().zip(kicks) {
+            let new_ke = delta_2_ke(particle[1], gamma0) + kick;
+            particle[1] = gamma_2_delta(ke_2_gamma(new_ke), gamma0);
+        }
     }
 }
 
-pub fn print_beam(sink: &mut impl Write, beam: &Beam) {
-    let num_electrons = beam.pos.len_of(Axis(0));
-    for e_num in 0..num_electrons {
-        let this_electron = beam.pos.slice(s![e_num, ..]);
-        if let Err(e) = writeln!(sink, "{}, {}", this_electron[0], this_electron[1]) {
-            println!("ERROR: {e}");
+/// Beam current (A) at each bin centre z (m)
+#[derive(Debug, Clone)]
+pub struct CurrentProfile {
+    pub z: Vec<f64>,
+    pub current: Vec<f64>,
+}
+
+impl CurrentProfile {
+    pub fn peak_current(&self) -> f64 {
+        self.current.iter().fold(0f64, |a, &b| a.max(b))
+    }
+
+    /// Full width (m) at half of the peak current, interpolated between bins
+    pub fn fwhm(&self) -> f64 {
+        let half_max = self.peak_current() / 2f64;
+        if half_max == 0f64 {
+            return 0f64;
+        }
+        let first = self.current.iter().position(|&i| i >= half_max).unwrap();
+        let last = self.current.iter().rposition(|&i| i >= half_max).unwrap();
+        let crossing = |inside: usize, outside: Option<usize>| match outside {
+            Some(outside) => {
+                let frac = (self.current[inside] - half_max)
+                    / (self.current[inside] - self.current[outside]);
+                self.z[inside] + frac * (self.z[outside] - self.z[inside])
+            }
+            None => self.z[inside],
+        };
+        let left = crossing(first, first.checked_sub(1));
+        let right = crossing(last, Some(last + 1).filter(|&i| i < self.current.len()));
+        right - left
+    }
+}
+
+pub fn print_current_profile(sink: &mut impl Write, profile: &CurrentProfile) -> io::Result<()> {
+    for (z, current) in profile.z.iter().zip(&profile.current) {
+        writeln!(sink, "{}, {}", z, current)?;
+    }
+    Ok(())
+}
+
+/// Weighted statistics of the longitudinal phase space. Lengths are in metres.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BeamStats {
+    pub mean_z: f64,
+    pub rms_z: f64,
+    pub mean_delta: f64,
+    pub rms_delta: f64,
+    /// Normalised correlation coefficient between z and delta
+    pub correlation: f64,
+    /// Slope of the least-squares linear fit of delta against z (1/m)
+    pub chirp: f64,
+    pub skewness_z: f64,
+    pub kurtosis_z: f64,
+    pub skewness_delta: f64,
+    pub kurtosis_delta: f64,
+    /// rms longitudinal emittance, sqrt(<z^2><delta^2> - <z delta>^2) (m)
+    pub emittance: f64,
+}
+
+impl BeamStats {
+    pub fn new(pos: ArrayView2<f64>, weights: ArrayView1<f64>) -> Self {
+        let total_weight = weights.sum();
+        if pos.len_of(Axis(0)) == 0 || total_weight == 0f64 {
+            return Default::default();
+        }
+        let z = pos.column(0);
+        let delta = pos.column(1);
+        let mean_z = weighted_mean(z, weights, total_weight);
+        let mean_delta = weighted_mean(delta, weights, total_weight);
+        let moment = |a: ArrayView1<f64>, mean_a: f64, b: ArrayView1<f64>, mean_b: f64| {
+            a.iter()
+                .zip(b)
+                .zip(weights)
+                .map(|((a, b), w)| w * (a - mean_a) * (b - mean_b))
+                .sum::<f64>()
+                / total_weight
+        };
+        let var_z = moment(z, mean_z, z, mean_z);
+        let var_delta = moment(delta, mean_delta, delta, mean_delta);
+        let cov = moment(z, mean_z, delta, mean_delta);
+        let rms_z = var_z.sqrt();
+        let rms_delta = var_delta.sqrt();
+
+        Self {
+            mean_z,
+            rms_z,
+            mean_delta,
+            rms_delta,
+            correlation: if var_z * var_delta > 0f64 {
+                cov / (rms_z * rms_delta)
+            } else {
+                0f64
+            },
+            chirp: if var_z > 0f64 { cov / var_z } else { 0f64 },
+            skewness_z: standardised_moment(z, weights, total_weight, mean_z, rms_z, 3),
+            kurtosis_z: standardised_moment(z, weights, total_weight, mean_z, rms_z, 4),
+            skewness_delta: standardised_moment(
+                delta,
+                weights,
+                total_weight,
+                mean_delta,
+                rms_delta,
+                3,
+            ),
+            kurtosis_delta: standardised_moment(
+                delta,
+                weights,
+                total_weight,
+                mean_delta,
+                rms_delta,
+                4,
+            ),
+            emittance: (var_z * var_delta - cov.powi(2)).max(0f64).sqrt(),
         }
     }
 }
 
+fn weighted_mean(vals: ArrayView1<f64>, weights: ArrayView1<f64>, total_weight: f64) -> f64 {
+    vals.iter().zip(weights).map(|(v, w)| v * w).sum::<f64>() / total_weight
+}
+
+/// The `order`th central moment divided by rms^order
+fn standardised_moment(
+    vals: ArrayView1<f64>,
+    weights: ArrayView1<f64>,
+    total_weight: f64,
+    mean: f64,
+    rms: f64,
+    order: i32,
+) -> f64 {
+    if rms == 0f64 {
+        return 0f64;
+    }
+    let moment = vals
+        .iter()
+        .zip(weights)
+        .map(|(v, w)| w * (v - mean).powi(order))
+        .sum::<f64>()
+        / total_weight;
+    moment / rms.powi(order)
+}
+
+pub fn print_stats(sink: &mut impl Write, stats: &BeamStats) -> io::Result<()> {
+    let rows = [
+        ("mean z (m)", stats.mean_z),
+        ("rms z (m)", stats.rms_z),
+        ("mean delta", stats.mean_delta),
+        ("rms delta", stats.rms_delta),
+        ("z-delta correlation", stats.correlation),
+        ("chirp (1/m)", stats.chirp),
+        ("skewness z", stats.skewness_z),
+        ("kurtosis z", stats.kurtosis_z),
+        ("skewness delta", stats.skewness_delta),
+        ("kurtosis delta", stats.kurtosis_delta),
+        ("emittance (m)", stats.emittance),
+    ];
+    for (name, val) in rows {
+        writeln!(sink, "{:<20}: {}", name, val)?;
+    }
+    Ok(())
+}
+
+/// A bunch streaked across a screen by a transverse deflecting cavity
+#[derive(Debug, Clone)]
+pub struct StreakedImage {
+    /// Position (m) of each particle on the screen
+    pub screen_pos: Array1<f64>,
+    pub ids: Array1<usize>,
+    /// Relative weight of each particle
+    pub weights: Array1<f64>,
+    /// Charge (C) of each particle
+    pub charges: Array1<f64>,
+    /// Change in screen position with z (m/m)
+    pub calibration: f64,
+}
+
+impl StreakedImage {
+    /// rms bunch length (m) measured from the rms size of the image
+    pub fn rms_length(&self) -> f64 {
+        let total: f64 = self.weights.sum();
+        if total == 0f64 || self.calibration == 0f64 {
+            return 0f64;
+        }
+        let mean = (&self.screen_pos * &self.weights).sum() / total;
+        let variance = (self.screen_pos.mapv(|y| (y - mean).powi(2)) * &self.weights).sum() / total;
+        variance.sqrt() / self.calibration.abs()
+    }
+}
+
+/// Writes the screen position (m), charge (C) and id of each particle in a streaked image
+pub fn print_streaked_image(sink: &mut impl Write, image: &StreakedImage) -> io::Result<()> {
+    let particles = image.screen_pos.iter().zip(&image.charges).zip(&image.ids);
+    for ((y, charge), id) in particles {
+        writeln!(sink, "{}, {}, {}", y, charge, id)?;
+    }
+    Ok(())
+}
+
+/// Writes the fraction of the charge still in the beam, and the number of particles lost in
+/// each element that lost any
+pub fn print_transmission(sink: &mut impl Write, beam: &Beam) -> io::Result<()> {
+    let mut losses: Vec<(&ParticleLoss, usize)> = Vec::new();
+    for loss in beam.lost.iter().flatten() {
+        match losses
+            .iter_mut()
+            .find(|(seen, _)| seen.element == loss.element)
+        {
+            Some((_, count)) => *count += 1,
+            None => losses.push((loss, 1)),
+        }
+    }
+    losses.sort_by_key(|(loss, _)| loss.element);
+    let num_lost: usize = losses.iter().map(|(_, count)| count).sum();
+    writeln!(
+        sink,
+        "Transmission: {} ({} of {} particles lost)",
+        beam.transmission(),
+        num_lost,
+        beam.lost.len()
+    )?;
+    for (loss, count) in losses {
+        writeln!(sink, "{}, {}, {}", loss.element, loss.name, count)?;
+    }
+    Ok(())
+}
+
+/// Writes z, delta, charge and id of the particles still in the beam, in order of their ids
+pub fn print_beam(sink: &mut impl Write, beam: &Beam) -> io::Result<()> {
+    let charges = beam.particle_charges();
+    for e_num in beam.rows_by_id().into_iter().filter(|&i| beam.is_alive(i)) {
+        let this_electron = beam.pos.slice(s![e_num, ..]);
+        writeln!(
+            sink,
+            "{}, {}, {}, {}",
+            this_electron[0], this_electron[1], charges[e_num], beam.ids[e_num]
+        )?;
+    }
+    Ok(())
+}
+
+/// Change in z of a particle with energy deviation `delta` over a drift (`angle` of zero) or a
+/// sector dipole of path `length`. The particle's own velocity and bending radius are used, so
+/// nothing is expanded in `delta`.
+fn exact_path_delta_z(delta: f64, gamma0: f64, length: f64, angle: f64) -> f64 {
+    let gamma = delta_2_gamma(delta, gamma0);
+    let beta = gamma_2_beta(gamma);
+    let beta0 = gamma_2_beta(gamma0);
+    let path = if angle == 0f64 {
+        length
+    } else {
+        let rho = length / angle;
+        let p_ratio = (beta * gamma) / (beta0 * gamma0);
+        let exit_angle = angle - ((p_ratio - 1f64) * angle.sin() / p_ratio).asin();
+        rho * p_ratio * exit_angle
+    };
+    length - (beta0 / beta) * path
+}
+
 pub fn ke_2_gamma(ke: f64) -> f64 {
     ke / MASS + 1f64
 }
@@ -75,12 +782,12 @@ pub fn gamma_2_beta(g: f64) -> f64 {
     (1f64 - (1f64 / g.powi(2))).sqrt()
 }
 
-fn gamma_2_delta(gamma: f64, gamma0: f64) -> f64 {
+pub fn gamma_2_delta(gamma: f64, gamma0: f64) -> f64 {
     let beta0 = gamma_2_beta(gamma0);
     (1f64 / beta0) * ((gamma / gamma0) - 1f64)
 }
 
-fn delta_2_gamma(delta: f64, gamma0: f64) -> f64 {
+pub fn delta_2_gamma(delta: f64, gamma0: f64) -> f64 {
     let beta0 = gamma_2_beta(gamma0);
     beta0 * gamma0 * delta + gamma0
 }
@@ -93,6 +800,104 @@ fn delta_2_ke(delta: f64, gamma0: f64) -> f64 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn stats_of_correlated_beam() {
+        let beam = Beam::new(arr2(&[[-1f64, -2f64], [0f64, 0f64], [1f64, 2f64]]));
+        let stats = beam.stats();
+        assert_eq!(stats.mean_z, 0f64);
+        assert_eq!(stats.rms_z, (2f64 / 3f64).sqrt());
+        assert_eq!(stats.chirp, 2f64);
+        assert_eq!(stats.correlation, 1f64);
+        assert_eq!(stats.skewness_z, 0f64);
+        assert_eq!(stats.emittance, 0f64);
+    }
+
+    #[test]
+    fn stats_respect_weights() {
+        let mut beam = Beam::new(arr2(&[[0f64, 0f64], [1f64, 1f64]]));
+        beam.weights = Array1::from(vec![3f64, 1f64]);
+        let stats = beam.stats();
+        assert_eq!(stats.mean_z, 0.25);
+        assert_eq!(stats.mean_delta, 0.25);
+    }
+
+    #[test]
+    fn stats_leave_out_lost_particles() {
+        // A particle this far below the design energy cannot leave the dipole, and its exact path
+        // length is not a number
+        let dipole = crate::elements::make_dipole("dipole".to_string(), 1f64, 1.2, 100f64).unwrap();
+        let mut beam = Beam::new(arr2(&[[-1e-3, 0f64], [0f64, -0.9], [1e-3, 0f64]]));
+        beam.track_with_order(&dipole, TrackingOrder::Exact);
+        assert_eq!(beam.transmission(), 2f64 / 3f64);
+        let stats = beam.stats();
+        assert_eq!(stats.mean_z, beam.live_particles().stats().mean_z);
+        assert_eq!(stats.mean_delta, 0f64);
+        assert!(stats.rms_z.is_finite() && stats.emittance.is_finite());
+    }
+
+    #[test]
+    fn current_profile_integrates_to_charge() {
+        let mut beam = Beam::new(arr2(&[[0f64, 0f64], [1e-3, 0f64], [2e-3, 0f64]]));
+        beam.charge = 1e-9;
+        let profile = beam.current_profile(10, 0);
+        let bin_width = profile.z[1] - profile.z[0];
+        let total: f64 = profile.current.iter().map(|i| i * bin_width / C).sum();
+        assert!((total - beam.charge).abs() < 1e-20);
+    }
+
+    #[test]
+    fn fwhm_of_flat_profile() {
+        let profile = CurrentProfile {
+            z: vec![0f64, 1f64, 2f64, 3f64, 4f64],
+            current: vec![0f64, 2f64, 2f64, 2f64, 0f64],
+        };
+        assert_eq!(profile.peak_current(), 2f64);
+        assert_eq!(profile.fwhm(), 3f64);
+    }
+
+    #[test]
+    fn tracking_keeps_the_beam_sorted() {
+        use crate::elements::make_drift;
+        // The lower energy particle starts ahead, but is overtaken in the drift
+        let mut beam = Beam::new(arr2(&[[0f64, 0.01], [1e-6, -0.01], [-1f64, 0f64]]));
+        beam.weights = Array1::from(vec![1f64, 2f64, 3f64]);
+        let drift = make_drift("drift".to_string(), 1f64, 10f64);
+        beam.track(&drift);
+        assert!(beam.is_sorted_by_z());
+        assert_eq!(beam.ids, Array1::from(vec![2, 1, 0]));
+        assert_eq!(beam.weights, Array1::from(vec![3f64, 2f64, 1f64]));
+        assert_eq!(
+            beam.pos_by_id().column(1),
+            arr2(&[[0.01], [-0.01], [0f64]]).column(0)
+        );
+        assert_eq!(beam.row_of(0), Some(2));
+    }
+
+    #[test]
+    fn exact_path_is_zero_for_reference_particle() {
+        assert_eq!(exact_path_delta_z(0f64, 500f64, 2f64, 0f64), 0f64);
+        assert!(exact_path_delta_z(0f64, 500f64, 2f64, 0.3).abs() < 1e-15);
+    }
+
+    #[test]
+    fn exact_dipole_path_agrees_with_r56_for_small_delta() {
+        let gamma0 = 500f64;
+        let (length, angle) = (2f64, 0.3);
+        let rho = length / angle;
+        let beta0 = gamma_2_beta(gamma0);
+        let r56 = length / (beta0 * gamma0).powi(2) - rho * (angle - angle.sin()) / beta0.powi(2);
+        let delta = 1e-6;
+        let dz = exact_path_delta_z(delta, gamma0, length, angle);
+        assert!((dz / delta - r56).abs() < 1e-5 * r56.abs());
+    }
+
+    #[test]
+    fn tracking_order_can_be_parsed() {
+        assert_eq!("exact".parse(), Ok(TrackingOrder::Exact));
+        assert_eq!("1".parse(), Ok(TrackingOrder::Linear));
+        assert!("4".parse::<TrackingOrder>().is_err());
+    }
+
     #[test]
     fn ke_of_restmass_has_gamma_two() {
         let ke = MASS;
@@ -0,0 +1,128 @@
+use crate::beam::C;
+use crate::wakefield::Z0;
+use ndarray::ArrayView1;
+use std::f64::consts::PI;
+
+/// Number of slices used for the CSR wake when the lattice does not give one
+pub const CSR_BINS: usize = 100;
+/// Half-width (in bins) of the smoothing when the lattice does not give one, as elegant's
+/// `SG_HALFWIDTH`
+pub const CSR_SMOOTHING: usize = 4;
+
+/// 1-D steady-state coherent synchrotron radiation in a dipole
+#[derive(Debug, Clone, PartialEq)]
+pub struct Csr {
+    /// Number of slices used to find the line density
+    pub bins: usize,
+    /// Half-width (in bins) of the moving average applied to the line density
+    pub smoothing: usize,
+}
+
+impl Default for Csr {
+    fn default() -> Self {
+        Self {
+            bins: CSR_BINS,
+            smoothing: CSR_SMOOTHING,
+        }
+    }
+}
+
+impl Csr {
+    /// Energy change (eV) of each particle at position `z` caused by a bunch of total `charge`
+    /// (C), shared according to `weights`, travelling `length` metres on a circle of `radius`.
+    /// Particles with larger `z` are ahead, and see the radiation of those behind them.
+    pub fn energy_kicks(
+        &self,
+        z: ArrayView1<f64>,
+        weights: ArrayView1<f64>,
+        charge: f64,
+        radius: f64,
+        length: f64,
+    ) -> Vec<f64> {
+        let num_particles = z.len();
+        let total_weight = weights.sum();
+        let bins = self.bins.max(1);
+        if num_particles == 0 || charge == 0f64 || total_weight == 0f64 || radius == 0f64 {
+            return vec![0f64; num_particles];
+        }
+        let z_min = z.fold(f64::INFINITY, |a, &b| a.min(b));
+        let z_max = z.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let bin_width = (z_max - z_min) / bins as f64;
+        if bin_width == 0f64 {
+            return vec![0f64; num_particles];
+        }
+        let bin_of = |z: f64| (((z - z_min) / bin_width) as usize).min(bins - 1);
+
+        let mut fraction = vec![0f64; bins];
+        for (&z_val, &weight) in z.iter().zip(weights) {
+            fraction[bin_of(z_val)] += weight / total_weight;
+        }
+        let density: Vec<f64> = (0..bins)
+            .map(|bin| {
+                let low = bin.saturating_sub(self.smoothing);
+                let high = (bin + self.smoothing).min(bins - 1);
+                let summed: f64 = fraction[low..=high].iter().sum();
+                summed / ((high - low + 1) as f64 * bin_width)
+            })
+            .collect();
+        let derivative: Vec<f64> = (0..bins)
+            .map(|bin| {
+                let low = bin.saturating_sub(1);
+                let high = (bin + 1).min(bins - 1);
+                (density[high] - density[low]) / ((high - low).max(1) as f64 * bin_width)
+            })
+            .collect();
+
+        // dE/ds = -2 Q / (4 pi eps0 3^(1/3) R^(2/3)) * integral of lambda'(z') (z - z')^(-1/3)
+        // over the particles behind. The kernel is integrated exactly across each bin, and the
+        // witness bin contributes up to its centre.
+        let kernel = |near: f64, far: f64| 1.5 * (far.powf(2f64 / 3f64) - near.powf(2f64 / 3f64));
+        let prefactor = -2f64 * charge * Z0 * C
+            / (4f64 * PI)
+            / (3f64.powf(1f64 / 3f64) * radius.abs().powf(2f64 / 3f64));
+        let mut energy_loss_rate = vec![0f64; bins];
+        for (witness, rate) in energy_loss_rate.iter_mut().enumerate() {
+            let mut integral = derivative[witness] * kernel(0f64, 0.5 * bin_width);
+            for (source, deriv) in derivative.iter().enumerate().take(witness) {
+                let near = (witness - source) as f64 * bin_width - 0.5 * bin_width;
+                integral += deriv * kernel(near, near + bin_width);
+            }
+            *rate = prefactor * integral;
+        }
+
+        z.iter()
+            .map(|&z_val| length * energy_loss_rate[bin_of(z_val)])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array1;
+
+    fn gaussian_bunch() -> (Array1<f64>, Array1<f64>) {
+        let z = Array1::linspace(-5e-4, 5e-4, 2001);
+        let weights = z.mapv(|z: f64| (-(z / 1e-4).powi(2) / 2f64).exp());
+        (z, weights)
+    }
+
+    #[test]
+    fn zero_charge_gives_no_kick() {
+        let (z, weights) = gaussian_bunch();
+        let kicks = Csr::default().energy_kicks(z.view(), weights.view(), 0f64, 10f64, 0.5);
+        assert!(kicks.iter().all(|&kick| kick == 0f64));
+    }
+
+    #[test]
+    fn head_gains_and_core_loses_energy() {
+        let (z, weights) = gaussian_bunch();
+        let kicks = Csr::default().energy_kicks(z.view(), weights.view(), 100e-12, 10f64, 0.5);
+        let head = kicks[1500];
+        let core = kicks[1000];
+        assert!(head > 0f64);
+        assert!(core < 0f64);
+        let mean: f64 = kicks.iter().zip(&weights).map(|(k, w)| k * w).sum::<f64>() / weights.sum();
+        assert!(mean < 0f64);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::beam::{gamma_2_delta, gamma_2_ke, ke_2_gamma, Beam};
+use crate::error::{FileLoc, LotrError};
+use crate::sdds::beam_from_t_and_p;
+use ndarray::{Array1, Array2};
+use std::fs::read_to_string;
+
+/// Loads a beam from a CSV file whose first line names its columns. The longitudinal
+/// coordinates may be given as any of
+///  * `t` (s) and `p` (beta gamma), as exported from elegant, with z measured from the mean
+///    arrival time,
+///  * `z` (m) and `delta`,
+///  * `z` (m) and `dE`, the energy deviation (eV) from the reference,
+///
+/// and an optional `weight` column gives the relative weight of each particle. Other columns,
+/// such as elegant's `x`, `xp`, `y` and `yp`, are ignored.
+///
+/// The reference kinetic energy `reference_ke` (eV) is that of the delta or dE columns. Without
+/// one, the reference is the mean energy of the particles, which needs the absolute momenta of
+/// `t` and `p`. Returns the beam and its reference kinetic energy.
+pub fn load_csv_beam(filename: &str, reference_ke: Option<f64>) -> Result<(Beam, f64), LotrError> {
+    let contents = read_to_string(filename).map_err(|source| LotrError::Io {
+        filename: filename.to_string(),
+        source,
+    })?;
+    let loc = |row: usize| FileLoc {
+        filename: filename.to_string(),
+        row,
+        col: 1,
+    };
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let split = |line: &str| -> Vec<String> {
+        line.split(',')
+            .map(|field| field.trim().trim_matches('"').to_string())
+            .collect()
+    };
+    let Some((_, header)) = lines.next() else {
+        return Err(LotrError::syntax(&loc(1), "The CSV file is empty"));
+    };
+    let header = split(header);
+
+    let mut columns: Vec<Vec<f64>> = vec![vec![]; header.len()];
+    for (row, line) in lines {
+        let fields = split(line);
+        if fields.len() != header.len() {
+            return Err(LotrError::syntax(
+                &loc(row + 1),
+                format!("Expected {} values, got {}", header.len(), fields.len()),
+            ));
+        }
+        for (column, field) in columns.iter_mut().zip(&fields) {
+            let value = field.parse().map_err(|_| {
+                LotrError::syntax(&loc(row + 1), format!("Cannot read '{field}' as a number"))
+            })?;
+            column.push(value);
+        }
+    }
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|col| col.eq_ignore_ascii_case(name))
+            .map(|ind| columns[ind].as_slice())
+    };
+    let needs_reference = |columns: &str| {
+        LotrError::syntax(
+            &loc(1),
+            format!("A beam given by {columns} needs an explicit reference energy"),
+        )
+    };
+
+    let (mut beam, ke) = match (column("t"), column("p"), column("z")) {
+        (Some(t), Some(p), _) => {
+            let gamma0 = reference_ke.map(ke_2_gamma);
+            let (beam, gamma0) = beam_from_t_and_p(t, p, gamma0, true);
+            (beam, gamma_2_ke(gamma0))
+        }
+        (_, _, Some(z)) => {
+            let delta = if let Some(delta) = column("delta") {
+                reference_ke.ok_or_else(|| needs_reference("z and delta"))?;
+                delta.to_vec()
+            } else if let Some(energy_deviation) = column("dE") {
+                let ke = reference_ke.ok_or_else(|| needs_reference("z and dE"))?;
+                let gamma0 = ke_2_gamma(ke);
+                energy_deviation
+                    .iter()
+                    .map(|de| gamma_2_delta(ke_2_gamma(ke + de), gamma0))
+                    .collect()
+            } else {
+                return Err(LotrError::syntax(
+                    &loc(1),
+                    "A 'z' column needs a 'delta' or a 'dE' column to go with it",
+                ));
+            };
+            let mut pos = Array2::zeros((z.len(), 2));
+            pos.column_mut(0).assign(&Array1::from_vec(z.to_vec()));
+            pos.column_mut(1).assign(&Array1::from_vec(delta));
+            (Beam::new(pos), reference_ke.unwrap())
+        }
+        _ => {
+            return Err(LotrError::syntax(
+                &loc(1),
+                "Expected columns named 't' and 'p', 'z' and 'delta', or 'z' and 'dE'",
+            ))
+        }
+    };
+    if let Some(weights) = column("weight") {
+        beam.weights = Array1::from_vec(weights.to_vec());
+    }
+    Ok((beam, ke))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdds::load_sdds_beam;
+
+    #[test]
+    fn elegant_csv_matches_its_sdds() {
+        let (csv, ke) = load_csv_beam("tests/elegant_test_runs/input/test_beam.csv", None).unwrap();
+        let (sdds, gamma0) =
+            load_sdds_beam("tests/elegant_test_runs/input/test_beam.sdds", None, true).unwrap();
+        assert!((ke_2_gamma(ke) - gamma0).abs() < 1e-6 * gamma0);
+        assert_eq!(csv.pos.nrows(), sdds.pos.nrows());
+        for (a, b) in csv.pos.iter().zip(sdds.pos.iter()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn z_with_delta_or_energy_deviation() {
+        let (beam, ke) = load_csv_beam("tests/z_delta_beam.csv", Some(100e6)).unwrap();
+        assert_eq!(ke, 100e6);
+        assert_eq!(beam.pos.row(1).to_vec(), [1e-3, 0.01]);
+        assert_eq!(beam.weights.to_vec(), [1f64, 2f64, 1f64]);
+
+        let (beam, _) = load_csv_beam("tests/z_de_beam.csv", Some(100e6)).unwrap();
+        let expected = gamma_2_delta(ke_2_gamma(101e6), ke_2_gamma(100e6));
+        assert_eq!(beam.pos[[1, 0]], 1e-3);
+        assert!((beam.pos[[1, 1]] - expected).abs() < 1e-15);
+
+        assert!(matches!(
+            load_csv_beam("tests/z_delta_beam.csv", None),
+            Err(LotrError::Syntax { .. })
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::beam::{gamma_2_beta, ke_2_gamma};
+use crate::random::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZDistribution {
+    Gaussian,
+    Uniform,
+    FlatTop,
+}
+
+/// Recipe for a generated beam. Lengths are in metres and energies in eV.
+#[derive(Debug, Clone)]
+pub struct BeamDistribution {
+    pub num_particles: usize,
+    pub seed: u64,
+    pub z_dist: ZDistribution,
+    /// rms bunch length of a gaussian bunch
+    pub sigma_z: f64,
+    /// Full length of a uniform or flat-top bunch
+    pub length_z: f64,
+    /// rms length of the gaussian edges of a flat-top bunch
+    pub rise_z: f64,
+    /// Uncorrelated rms energy spread
+    pub sigma_e: f64,
+    /// Linear energy-z correlation (eV/m)
+    pub chirp: f64,
+    /// Quadratic energy-z correlation (eV/m^2)
+    pub curvature: f64,
+}
+
+impl Default for BeamDistribution {
+    fn default() -> Self {
+        Self {
+            num_particles: 1000,
+            seed: 1,
+            z_dist: ZDistribution::Gaussian,
+            sigma_z: 1e-3,
+            length_z: 1e-3,
+            rise_z: 0f64,
+            sigma_e: 0f64,
+            chirp: 0f64,
+            curvature: 0f64,
+        }
+    }
+}
+
+impl BeamDistribution {
+    /// Returns the (z, delta) coordinates of the particles, with delta defined relative to a
+    /// reference particle with kinetic energy `design_ke`.
+    pub fn generate(&self, design_ke: f64) -> Vec<[f64; 2]> {
+        let design_beta = gamma_2_beta(ke_2_gamma(design_ke));
+        let mut rng = Rng::new(self.seed);
+        (0..self.num_particles)
+            .map(|_| {
+                let z = match self.z_dist {
+                    ZDistribution::Gaussian => self.sigma_z * rng.gaussian(),
+                    ZDistribution::Uniform => self.length_z * (rng.uniform() - 0.5),
+                    ZDistribution::FlatTop => {
+                        self.length_z * (rng.uniform() - 0.5) + self.rise_z * rng.gaussian()
+                    }
+                };
+                let del_e =
+                    self.chirp * z + self.curvature * z.powi(2) + self.sigma_e * rng.gaussian();
+                [z, (1f64 / design_beta) * (del_e / design_ke)]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_bunch_stays_within_its_length() {
+        let dist = BeamDistribution {
+            z_dist: ZDistribution::Uniform,
+            length_z: 2e-3,
+            ..Default::default()
+        };
+        let particles = dist.generate(100e6);
+        assert_eq!(particles.len(), dist.num_particles);
+        assert!(particles.iter().all(|p| p[0].abs() <= 1e-3));
+    }
+
+    #[test]
+    fn chirp_correlates_energy_with_z() {
+        let dist = BeamDistribution {
+            chirp: 10e6,
+            ..Default::default()
+        };
+        let design_ke = 100e6;
+        let design_beta = gamma_2_beta(ke_2_gamma(design_ke));
+        for p in dist.generate(design_ke) {
+            let del_e = p[1] * design_beta * design_ke;
+            assert!((del_e - 10e6 * p[0]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_beam() {
+        let dist = BeamDistribution {
+            sigma_e: 1e3,
+            ..Default::default()
+        };
+        assert_eq!(dist.generate(100e6), dist.generate(100e6));
+    }
+}
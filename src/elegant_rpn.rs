@@ -1,49 +1,306 @@
+use crate::beam::{C, ELECTRON_RADIUS, MASS};
 use std::collections::HashMap;
-use std::process::exit;
+use std::f64::consts::PI;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpnError {
+    /// The word needed more values than were on the stack
+    StackUnderflow(String),
+    UnrecognisedWord(String),
+    /// The expression left more than one value on the stack
+    TooManyValues(usize),
+    /// A '?' without its closing '$', or a ':' or 'sto' out of place
+    BadConditional(String),
+}
+
+impl fmt::Display for RpnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpnError::StackUnderflow(word) => {
+                write!(f, "Not enough values on the RPN stack for '{word}'")
+            }
+            RpnError::UnrecognisedWord(word) => write!(f, "Unrecognised token in rpnstr: {word}"),
+            RpnError::TooManyValues(num) => {
+                write!(f, "RPN expression left {num} values on the stack")
+            }
+            RpnError::BadConditional(word) => {
+                write!(f, "Badly formed RPN conditional at '{word}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RpnError {}
+
+/// The constants known to elegant's RPN calculator
+fn constant(word: &str) -> Option<f64> {
+    let val = match word {
+        "pi" => PI,
+        "mev" => MASS / 1e6,
+        "c_mks" => C,
+        "c_cgs" => C * 1e2,
+        "e_mks" => 1.602176634e-19,
+        "e_cgs" => 4.803204712570263e-10,
+        "me_mks" => 9.1093837015e-31,
+        "me_cgs" => 9.1093837015e-28,
+        "re_mks" => ELECTRON_RADIUS,
+        "re_cgs" => ELECTRON_RADIUS * 1e2,
+        "kb_mks" => 1.380649e-23,
+        "kb_cgs" => 1.380649e-16,
+        "hbar_mks" => 1.054571817e-34,
+        "hbar_mev" => 6.582119569e-22,
+        "mu_o" => 1.25663706212e-6,
+        "eps_o" => 8.8541878128e-12,
+        "na" => 6.02214076e23,
+        _ => return None,
+    };
+    Some(val)
+}
+
+/// Functions of the value on top of the stack
+fn unary(word: &str) -> Option<fn(f64) -> f64> {
+    let func: fn(f64) -> f64 = match word {
+        "sqrt" => f64::sqrt,
+        "sqr" => |a| a * a,
+        "exp" => f64::exp,
+        "ln" => f64::ln,
+        "sin" => f64::sin,
+        "cos" => f64::cos,
+        "tan" => f64::tan,
+        "asin" => f64::asin,
+        "acos" => f64::acos,
+        "atan" => f64::atan,
+        "sinh" => f64::sinh,
+        "cosh" => f64::cosh,
+        "tanh" => f64::tanh,
+        "asinh" => f64::asinh,
+        "acosh" => f64::acosh,
+        "atanh" => f64::atanh,
+        "abs" => f64::abs,
+        "chs" => |a| -a,
+        "rec" => f64::recip,
+        "int" => f64::trunc,
+        "floor" => f64::floor,
+        "ceil" => f64::ceil,
+        _ => return None,
+    };
+    Some(func)
+}
+
+/// Functions of the top two values on the stack, `b` having been pushed first
+fn binary(word: &str) -> Option<fn(f64, f64) -> f64> {
+    let func: fn(f64, f64) -> f64 = match word {
+        "+" => |b, a| b + a,
+        "-" => |b, a| b - a,
+        "*" => |b, a| b * a,
+        "/" => |b, a| b / a,
+        "pow" => f64::powf,
+        "mod" => |b, a| b % a,
+        "atan2" => f64::atan2,
+        "hypot" => f64::hypot,
+        "max2" => f64::max,
+        "min2" => f64::min,
+        _ => return None,
+    };
+    Some(func)
+}
+
+/// Comparisons of the top two values on the stack, `b` having been pushed first. The result goes
+/// on the logical stack.
+fn comparison(word: &str) -> Option<fn(f64, f64) -> bool> {
+    let func: fn(f64, f64) -> bool = match word {
+        "<" => |b, a| b < a,
+        ">" => |b, a| b > a,
+        "==" => |b, a| b == a,
+        "!=" => |b, a| b != a,
+        "<=" => |b, a| b <= a,
+        ">=" => |b, a| b >= a,
+        _ => return None,
+    };
+    Some(func)
+}
 
 #[derive(Default, Debug)]
 pub struct RpnCalculator {
     stack: Vec<f64>,
+    logic: Vec<bool>,
     pub mem: HashMap<String, f64>,
 }
 
 impl RpnCalculator {
-    pub fn interpret_string(&mut self, input: &str) -> Option<f64> {
-        let mut store = false;
-        for word in input.replace(',', " ").split_ascii_whitespace() {
+    pub fn interpret_string(&mut self, input: &str) -> Result<Option<f64>, RpnError> {
+        let result = self.interpret_words(input);
+        if result.is_err() {
+            self.stack.clear();
+        }
+        self.logic.clear();
+        result
+    }
+
+    fn interpret_words(&mut self, input: &str) -> Result<Option<f64>, RpnError> {
+        let input = input.replace(',', " ");
+        let words: Vec<&str> = input.split_ascii_whitespace().collect();
+        let mut ind = 0;
+        while ind < words.len() {
+            let word = words[ind];
+            ind += 1;
             if let Ok(val) = word.parse::<f64>() {
                 self.stack.push(val);
             } else if word == "sto" {
-                store = true;
-            } else if word == "+" {
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.stack.push(a + b);
-            } else if word == "*" {
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.stack.push(a * b);
-            } else if word == "/" {
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.stack.push(b / a);
-            } else if word == "sqrt" {
-                let a = self.stack.pop().unwrap();
-                self.stack.push(a.sqrt());
-            } else if self.mem.contains_key(word) {
-                self.stack.push(*self.mem.get(word).unwrap());
-            } else if word == "pi" {
-                self.stack.push(std::f64::consts::PI);
-            } else if store {
-                let a = self.stack.pop().unwrap();
-                self.mem.insert(word.to_string(), a);
+                let name = words
+                    .get(ind)
+                    .ok_or_else(|| RpnError::BadConditional(word.to_string()))?;
+                ind += 1;
+                let a = self.pop(word)?;
+                self.mem.insert(name.to_string(), a);
+            } else if let Some(func) = binary(word) {
+                let a = self.pop(word)?;
+                let b = self.pop(word)?;
+                self.stack.push(func(b, a));
+            } else if let Some(func) = unary(word) {
+                let a = self.pop(word)?;
+                self.stack.push(func(a));
+            } else if let Some(func) = comparison(word) {
+                let a = self.pop(word)?;
+                let b = self.pop(word)?;
+                self.logic.push(func(b, a));
+            } else if let Some(val) = self.mem.get(word) {
+                self.stack.push(*val);
+            } else if let Some(val) = constant(word) {
+                self.stack.push(val);
             } else {
-                println!("{:?}", self.mem);
-                eprintln!("Unrecognised token in rpnstr: {}", word);
-                exit(1);
+                match word {
+                    "dup" => {
+                        let a = self.pop(word)?;
+                        self.stack.extend([a, a]);
+                    }
+                    "swap" => {
+                        let a = self.pop(word)?;
+                        let b = self.pop(word)?;
+                        self.stack.extend([a, b]);
+                    }
+                    "pop" => {
+                        self.pop(word)?;
+                    }
+                    "!" => {
+                        let a = self.pop_logic(word)?;
+                        self.logic.push(!a);
+                    }
+                    "&&" | "||" => {
+                        let a = self.pop_logic(word)?;
+                        let b = self.pop_logic(word)?;
+                        self.logic.push(if word == "&&" { a && b } else { a || b });
+                    }
+                    // `cond ? if-true : if-false $`, where the else branch is optional
+                    "?" => {
+                        if !self.pop_logic(word)? {
+                            ind = skip_branch(&words, ind)?;
+                        }
+                    }
+                    // The end of the if-true branch
+                    ":" => {
+                        ind = skip_branch(&words, ind)?;
+                        if words[ind - 1] != "$" {
+                            return Err(RpnError::BadConditional(word.to_string()));
+                        }
+                    }
+                    "$" => {}
+                    _ => return Err(RpnError::UnrecognisedWord(word.to_string())),
+                }
             }
         }
-        assert!(self.stack.len() < 2);
-        self.stack.pop()
+        if self.stack.len() > 1 {
+            return Err(RpnError::TooManyValues(self.stack.len()));
+        }
+        Ok(self.stack.pop())
+    }
+
+    fn pop(&mut self, word: &str) -> Result<f64, RpnError> {
+        self.stack
+            .pop()
+            .ok_or_else(|| RpnError::StackUnderflow(word.to_string()))
+    }
+
+    fn pop_logic(&mut self, word: &str) -> Result<bool, RpnError> {
+        self.logic
+            .pop()
+            .ok_or_else(|| RpnError::StackUnderflow(word.to_string()))
+    }
+}
+
+/// Index of the word after the ':' or '$' that ends the branch of a conditional starting at
+/// `start`, stepping over any nested conditionals
+fn skip_branch(words: &[&str], start: usize) -> Result<usize, RpnError> {
+    let mut depth = 0;
+    for (ind, &word) in words.iter().enumerate().skip(start) {
+        match word {
+            "?" => depth += 1,
+            "$" if depth > 0 => depth -= 1,
+            ":" | "$" if depth == 0 => return Ok(ind + 1),
+            _ => {}
+        }
+    }
+    Err(RpnError::BadConditional("?".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_values_can_be_recalled() {
+        let mut calc: RpnCalculator = Default::default();
+        assert_eq!(calc.interpret_string("2 3 * sto six"), Ok(None));
+        assert_eq!(calc.interpret_string("six 2 /"), Ok(Some(3f64)));
+        assert_eq!(calc.interpret_string("7 sto six"), Ok(None));
+        assert_eq!(calc.interpret_string("six"), Ok(Some(7f64)));
+    }
+
+    #[test]
+    fn stack_underflow_is_an_error() {
+        let mut calc: RpnCalculator = Default::default();
+        assert_eq!(
+            calc.interpret_string("2 *"),
+            Err(RpnError::StackUnderflow("*".to_string()))
+        );
+        assert_eq!(calc.interpret_string("1"), Ok(Some(1f64)));
+        assert_eq!(
+            calc.interpret_string("1 ?"),
+            Err(RpnError::StackUnderflow("?".to_string()))
+        );
+    }
+
+    #[test]
+    fn elegant_words_are_understood() {
+        let mut calc: RpnCalculator = Default::default();
+        let mut eval = |input: &str| calc.interpret_string(input).unwrap().unwrap();
+        assert_eq!(eval("5 3 -"), 2f64);
+        assert_eq!(eval("2 10 pow"), 1024f64);
+        assert_eq!(eval("1 exp ln"), 1f64);
+        assert_eq!(eval("0 sin 0 cos +"), 1f64);
+        assert_eq!(eval("1 atan 4 * pi -"), 0f64);
+        assert_eq!(eval("3 chs abs"), 3f64);
+        assert_eq!(eval("4 rec"), 0.25);
+        assert_eq!(eval("3 dup *"), 9f64);
+        assert_eq!(eval("1 2 swap -"), 1f64);
+        assert_eq!(eval("1 2 pop"), 1f64);
+        assert!((eval("mev 1e6 *") - MASS).abs() < 1e-6);
+        assert_eq!(eval("c_mks"), C);
+    }
+
+    #[test]
+    fn conditionals_pick_a_branch() {
+        let mut calc: RpnCalculator = Default::default();
+        let mut eval = |input: &str| calc.interpret_string(input).unwrap().unwrap();
+        assert_eq!(eval("1 2 < ? 10 : 20 $"), 10f64);
+        assert_eq!(eval("1 2 > ? 10 : 20 $"), 20f64);
+        assert_eq!(eval("5 1 2 > ? 1 + $"), 5f64);
+        assert_eq!(eval("1 1 == 2 1 > && ? 1 0 > ? 3 : 4 $ : 5 $"), 3f64);
+        assert_eq!(eval("1 1 != ! ? 6 : 7 $"), 6f64);
+        assert_eq!(
+            calc.interpret_string("1 2 == ? 2"),
+            Err(RpnError::BadConditional("?".to_string()))
+        );
     }
 }
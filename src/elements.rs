@@ -1,27 +1,229 @@
-use crate::beam::{gamma_2_beta, C};
+use crate::beam::{gamma_2_beta, C, COMPTON_WAVELENGTH, ELECTRON_RADIUS, MASS};
+use crate::csr::Csr;
+use crate::error::LotrError;
+use crate::lsc::Lsc;
+use crate::random::Rng;
+use crate::wakefield::Wake;
 use core::fmt::Debug;
+use ndarray::ArrayView1;
 use std::collections::HashMap;
 use std::f64::consts::PI;
-use std::process::exit;
 
-#[derive(Debug, Copy, Clone)]
+/// How the phase of an accelerating cavity is given
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PhaseConvention {
+    /// Radians, with the crest at 0
+    #[default]
+    Crest,
+    /// Degrees, with the crest at 90 (as in elegant)
+    Elegant,
+}
+
+/// How the voltage of an accelerating cavity is given
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum VoltageConvention {
+    /// Volts per metre of cavity, so the reference particle gains V L cos(phase)
+    #[default]
+    Gradient,
+    /// Volts across the whole cavity (as in elegant)
+    Total,
+}
+
+#[derive(Debug, Clone)]
 pub struct AccCavDetails {
     pub length: f64,
     pub wavenumber: f64,
     pub frequency: f64,
     pub voltage: f64,
+    /// Phase of the reference particle, in `phase_convention`
     pub phase: f64,
+    pub phase_convention: PhaseConvention,
+    pub voltage_convention: VoltageConvention,
+    pub wake: Option<Wake>,
+}
+
+impl AccCavDetails {
+    /// Voltage (V) across the whole cavity
+    pub fn total_voltage(&self) -> f64 {
+        match self.voltage_convention {
+            VoltageConvention::Gradient => self.voltage * self.length,
+            VoltageConvention::Total => self.voltage,
+        }
+    }
+
+    /// Phase (radians) of the reference particle relative to the crest
+    pub fn crest_phase(&self) -> f64 {
+        match self.phase_convention {
+            PhaseConvention::Crest => self.phase,
+            PhaseConvention::Elegant => self.phase.to_radians() - PI / 2f64,
+        }
+    }
+
+    /// Energy (eV) gained by the reference particle. Negative in a decelerating cavity,
+    /// such as a harmonic linearizer.
+    pub fn design_energy_gain(&self) -> f64 {
+        self.total_voltage() * self.crest_phase().cos()
+    }
+}
+
+/// A transverse deflecting cavity. The longitudinal phase space only drifts through it, but the
+/// deflection streaks the bunch across a downstream screen.
+#[derive(Debug, Clone)]
+pub struct DeflectorDetails {
+    pub length: f64,
+    pub wavenumber: f64,
+    pub frequency: f64,
+    pub voltage: f64,
+    /// Phase of the reference particle, in `phase_convention`, where the crest is the peak
+    /// deflection
+    pub phase: f64,
+    pub phase_convention: PhaseConvention,
+}
+
+impl DeflectorDetails {
+    /// Phase (radians) of the reference particle relative to the peak deflection
+    pub fn crest_phase(&self) -> f64 {
+        match self.phase_convention {
+            PhaseConvention::Crest => self.phase,
+            PhaseConvention::Elegant => self.phase.to_radians() - PI / 2f64,
+        }
+    }
+
+    /// Position (m) on a screen `r34` (m/rad) downstream of a particle at `z` with `gamma`
+    pub fn screen_position(&self, z: f64, gamma: f64, r34: f64) -> f64 {
+        let momentum = gamma_2_beta(gamma) * gamma * MASS;
+        let kick = self.voltage / momentum * (self.crest_phase() - self.wavenumber * z).cos();
+        r34 * kick
+    }
+
+    /// Change in screen position with z (m/m) about the reference particle, which has `gamma`.
+    /// Dividing a streaked image by this gives the longitudinal profile.
+    pub fn streak_calibration(&self, gamma: f64, r34: f64) -> f64 {
+        let momentum = gamma_2_beta(gamma) * gamma * MASS;
+        r34 * self.voltage / momentum * self.wavenumber * self.crest_phase().sin()
+    }
+}
+
+/// Limits on the energy deviation of the particles let through an energy collimator or scraper
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollimatorDetails {
+    pub delta_min: f64,
+    pub delta_max: f64,
+    /// Horizontal aperture (m) from which the limits follow once the dispersion at the element is
+    /// known, for the apertures of elegant's ECOL, RCOL and SCRAPER
+    pub aperture: Option<(f64, f64)>,
+}
+
+impl Default for CollimatorDetails {
+    fn default() -> Self {
+        Self {
+            delta_min: f64::NEG_INFINITY,
+            delta_max: f64::INFINITY,
+            aperture: None,
+        }
+    }
+}
+
+impl CollimatorDetails {
+    /// Limits for an aperture from `x_min` to `x_max` (m) where the dispersion is `dispersion`
+    /// (m). With no dispersion, every particle is let through.
+    pub fn from_aperture(x_min: f64, x_max: f64, dispersion: f64) -> Self {
+        let mut details = Self {
+            aperture: Some((x_min, x_max)),
+            ..Default::default()
+        };
+        details.set_dispersion(dispersion);
+        details
+    }
+
+    /// Sets the limits of an aperture from the dispersion (m) at the element. Limits not given by
+    /// an aperture are left alone.
+    pub fn set_dispersion(&mut self, dispersion: f64) {
+        let Some((x_min, x_max)) = self.aperture else {
+            return;
+        };
+        if dispersion == 0f64 {
+            self.delta_min = f64::NEG_INFINITY;
+            self.delta_max = f64::INFINITY;
+            return;
+        }
+        let (a, b) = (x_min / dispersion, x_max / dispersion);
+        self.delta_min = a.min(b);
+        self.delta_max = a.max(b);
+    }
+
+    pub fn accepts(&self, delta: f64) -> bool {
+        delta >= self.delta_min && delta <= self.delta_max
+    }
+}
+
+/// Sinusoidal energy modulation from a laser co-propagating with the beam in a wiggler
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaserHeater {
+    /// Laser wavelength (m)
+    pub wavelength: f64,
+    /// Peak energy modulation (eV)
+    pub amplitude: f64,
+    /// Full width (radians) of the uniformly distributed random phase slippage of each particle
+    /// relative to the laser. Zero gives a coherent modulation, 2 pi a pure energy spread.
+    pub phase_spread: f64,
+    pub seed: u64,
+}
+
+impl LaserHeater {
+    /// Energy change (eV) of each particle at position `z`
+    pub fn energy_kicks(&self, z: ArrayView1<f64>) -> Vec<f64> {
+        let wavenumber = 2f64 * PI / self.wavelength;
+        let mut rng = Rng::new(self.seed);
+        z.iter()
+            .map(|&z| {
+                let slippage = self.phase_spread * (rng.uniform() - 0.5);
+                self.amplitude * (wavenumber * z + slippage).sin()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WigglerDetails {
+    /// Deflection parameter
+    pub k: f64,
+    /// Period (m)
+    pub period: f64,
+    pub laser_heater: Option<LaserHeater>,
+}
+
+impl WigglerDetails {
+    /// Deflection parameter of a wiggler with a peak field of `b_field` (T) and `period` (m)
+    pub fn k_from_field(b_field: f64, period: f64) -> f64 {
+        b_field * period * C / (2f64 * PI * MASS)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DriftDetails {
+    pub lsc: Option<Lsc>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DipoleDetails {
+    pub csr: Option<Csr>,
+    /// Mean energy loss to incoherent synchrotron radiation
+    pub synch_rad: bool,
+    /// Seed for the random growth of the energy spread from synchrotron radiation, if it is on
+    pub isr_seed: Option<u64>,
 }
 
 #[derive(Debug)]
 pub enum EleType {
-    Drift,
-    Dipole,
+    Drift(DriftDetails),
+    Dipole(DipoleDetails),
     AccCav(AccCavDetails),
+    Deflector(DeflectorDetails),
+    Collimator(CollimatorDetails),
+    Wiggler(WigglerDetails),
 }
 
-// TODO(#2): Beam should (?) be resorted when tracked by an element that may reorder things.
-// Which elements could reorder particles? Dipoles.  AccCavs, but not in the linear approx.
 // TODO(#3): Add various diag elements that act on the beam as drifts, but produce side-effects.
 #[derive(Debug)]
 pub struct Element {
@@ -33,29 +235,101 @@ pub struct Element {
     pub params: HashMap<String, f64>,
 }
 
+/// Second- and third-order path-length terms (T566, U5666) of a particle moving at a different
+/// velocity to the reference over `length`.
+fn velocity_path_terms(length: f64, gamma: f64) -> (f64, f64) {
+    let beta_sq = gamma_2_beta(gamma).powi(2);
+    let gamma_sq = gamma.powi(2);
+    let t566 = -(length / gamma_sq) * (1f64 + beta_sq / 2f64);
+    let u5666 = (length / gamma_sq) * (1f64 + beta_sq / 2f64 + beta_sq.powi(2) / 2f64);
+    (t566, u5666)
+}
+
+impl Element {
+    /// Energy (eV) lost by the reference particle in this element
+    pub fn design_energy_loss(&self) -> f64 {
+        match &self.ele_type {
+            EleType::Dipole(details) if details.synch_rad => {
+                synchrotron_radiation_loss(self.gamma, self.length, self.params["angle"])
+            }
+            _ => 0f64,
+        }
+    }
+
+    /// Whether particles can overtake each other in this element. Anything with a length has a
+    /// path length that depends on the energy.
+    pub fn can_reorder(&self) -> bool {
+        self.length != 0f64
+    }
+
+    /// gamma of the reference particle at the exit of this element
+    pub fn exit_gamma(&self) -> f64 {
+        let energy_gain = match &self.ele_type {
+            EleType::AccCav(details) => details.design_energy_gain(),
+            _ => 0f64,
+        };
+        self.gamma + (energy_gain - self.design_energy_loss()) / MASS
+    }
+}
+
+/// Mean energy (eV) radiated by a particle with `gamma` bending through `angle` in `length`
+pub fn synchrotron_radiation_loss(gamma: f64, length: f64, angle: f64) -> f64 {
+    (2f64 / 3f64) * ELECTRON_RADIUS * MASS * gamma.powi(4) * angle.powi(2) / length
+}
+
+/// rms spread (eV) of the energy radiated by a particle with `gamma` bending through `angle` in
+/// `length`, from the quantum nature of the emission
+pub fn synchrotron_radiation_spread(gamma: f64, length: f64, angle: f64) -> f64 {
+    let variance = (55f64 / (24f64 * 3f64.sqrt()))
+        * ELECTRON_RADIUS
+        * COMPTON_WAVELENGTH
+        * MASS.powi(2)
+        * gamma.powi(7)
+        * angle.abs().powi(3)
+        / length.powi(2);
+    variance.sqrt()
+}
+
 pub fn make_drift(name: String, length: f64, gamma: f64) -> Element {
     let beta_sq = gamma_2_beta(gamma).powi(2);
     let gamma_sq = gamma.powi(2);
     let r56 = length / (beta_sq * gamma_sq);
+    let (t566, u5666) = velocity_path_terms(length, gamma);
     let mut param_map = HashMap::new();
     param_map.insert("r56".to_string(), r56);
+    param_map.insert("t566".to_string(), t566);
+    param_map.insert("u5666".to_string(), u5666);
     Element {
         name,
-        ele_type: EleType::Drift,
+        ele_type: EleType::Drift(Default::default()),
         length,
         gamma,
         params: param_map,
     }
 }
 
+/// A drift in which the beam is also kicked by its longitudinal space charge
+pub fn make_lsc_drift(name: String, length: f64, gamma: f64, lsc: Lsc) -> Element {
+    let mut drift = make_drift(name, length, gamma);
+    drift.ele_type = EleType::Drift(DriftDetails { lsc: Some(lsc) });
+    drift
+}
+
 pub fn make_quad(name: String, length: f64, gamma: f64) -> Element {
     make_drift(name, length, gamma)
 }
 
-pub fn make_dipole(name: String, length: f64, angle: f64, gamma: f64) -> Element {
-    if length == 0f64 {
-        eprintln!("Path length through a dipole should not be negative or zero");
-        exit(1);
+pub fn make_dipole(
+    name: String,
+    length: f64,
+    angle: f64,
+    gamma: f64,
+) -> Result<Element, LotrError> {
+    if length <= 0f64 {
+        return Err(LotrError::InvalidElement {
+            name,
+            message: "Path length through a dipole should not be negative or zero".to_string(),
+        });
     }
     let angle_fixed = if angle == 0f64 {
         f64::MIN_POSITIVE
@@ -67,20 +341,25 @@ pub fn make_dipole(name: String, length: f64, angle: f64, gamma: f64) -> Element
     let gamma_sq = gamma.powi(2);
     let r56 =
         (length / (beta_sq * gamma_sq)) - ((angle_fixed - angle_fixed.sin()) / (omega * beta_sq));
+    // The path length through a sector bend has no second-order term for a particle entering on
+    // axis; the third-order term is rho * sin^3(angle) / 6.
+    let (t566, velocity_u5666) = velocity_path_terms(length, gamma);
+    let u5666 = velocity_u5666 + angle_fixed.sin().powi(3) / (6f64 * omega);
 
     let mut param_map = HashMap::new();
     param_map.insert("angle".to_string(), angle);
     param_map.insert("r56".to_string(), r56);
-    Element {
+    param_map.insert("t566".to_string(), t566);
+    param_map.insert("u5666".to_string(), u5666);
+    Ok(Element {
         name,
-        ele_type: EleType::Dipole,
+        ele_type: EleType::Dipole(Default::default()),
         length,
         gamma,
         params: param_map,
-    }
+    })
 }
 
-// TODO(#4): Accelerating cavities need to have wakefields in their physics.
 // pub fn make_acccav(name: String, length: f64, v: f64, freq: f64, phi: f64, gamma: f64) -> Element {
 pub fn make_acccav(name: String, details: AccCavDetails, gamma: f64) -> Element {
     let length = details.length;
@@ -96,6 +375,9 @@ pub fn make_acccav(name: String, details: AccCavDetails, gamma: f64) -> Element
         frequency: freq,
         voltage: v,
         phase: phi,
+        phase_convention: details.phase_convention,
+        voltage_convention: details.voltage_convention,
+        wake: details.wake,
     };
 
     Element {
@@ -107,20 +389,55 @@ pub fn make_acccav(name: String, details: AccCavDetails, gamma: f64) -> Element
     }
 }
 
+/// A planar wiggler. The wiggling lengthens the path of lower energy particles, adding
+/// K^2 L / (2 gamma^2) to the R56 of a drift.
+pub fn make_wiggler(name: String, length: f64, gamma: f64, details: WigglerDetails) -> Element {
+    let mut wiggler = make_drift(name, length, gamma);
+    let r56 = details.k.powi(2) * length / (2f64 * gamma.powi(2));
+    for (key, term) in [("r56", r56), ("t566", -1.5 * r56), ("u5666", 2f64 * r56)] {
+        *wiggler.params.get_mut(key).unwrap() += term;
+    }
+    wiggler.ele_type = EleType::Wiggler(details);
+    wiggler
+}
+
+/// An energy collimator or scraper, which removes the particles outside its limits and otherwise
+/// acts as a drift
+pub fn make_collimator(
+    name: String,
+    length: f64,
+    gamma: f64,
+    details: CollimatorDetails,
+) -> Element {
+    let mut collimator = make_drift(name, length, gamma);
+    collimator.ele_type = EleType::Collimator(details);
+    collimator
+}
+
+/// A transverse deflecting cavity, which acts on the longitudinal phase space as a drift
+pub fn make_deflector(name: String, details: DeflectorDetails, gamma: f64) -> Element {
+    let mut deflector = make_drift(name, details.length, gamma);
+    deflector.ele_type = EleType::Deflector(DeflectorDetails {
+        wavenumber: 2f64 * PI * details.frequency / C,
+        ..details
+    });
+    deflector
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::beam::Beam;
+    use crate::beam::{ke_2_gamma, Beam, ParticleLoss, TrackingOrder};
 
     use super::*;
     use approx::assert_ulps_eq;
-    use ndarray::Array2; // for floating point tests
+    use ndarray::{Array1, Array2}; // for floating point tests
     const GAMMA0: f64 = 3000f64;
 
     #[test]
     fn dipole_does_not_affect_energy_error() {
         let b_field = 2.0;
         let angle = 0.7;
-        let dipole = make_dipole("dipole".to_string(), b_field, angle, GAMMA0);
+        let dipole = make_dipole("dipole".to_string(), b_field, angle, GAMMA0).unwrap();
         for e_error in [-0.01, -0.005, -0.001, 0.0, 0.001, 0.005, 0.01] {
             for z in [-5e-3, -1e-3, 0.0, 1e-3, 5e-3] {
                 let beam_vec = Array2::from(vec![[z, (1f64 / gamma_2_beta(GAMMA0)) * e_error]]);
@@ -131,6 +448,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn drift_has_ultrarelativistic_higher_order_terms() {
+        let drift = make_drift("drift".to_string(), 2f64, GAMMA0);
+        let r56 = drift.params["r56"];
+        assert_ulps_eq!(drift.params["t566"], -1.5 * r56, epsilon = 1e-12);
+        assert_ulps_eq!(drift.params["u5666"], 2f64 * r56, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn drift_path_length_is_not_linear() {
+        let drift = make_drift("drift".to_string(), 2f64, 10f64);
+        let delta = 0.01;
+        let mut beam = Beam::new(Array2::from(vec![[0f64, delta], [0f64, -delta]]));
+        beam.track_with_order(&drift, TrackingOrder::Second);
+        let expected = drift.params["t566"] * delta.powi(2);
+        assert_ulps_eq!(beam.pos[[0, 0]] + beam.pos[[1, 0]], 2f64 * expected);
+    }
+
+    #[test]
+    fn zero_length_dipole_is_an_error() {
+        assert!(make_dipole("dipole".to_string(), 0f64, 0.1, GAMMA0).is_err());
+    }
+
     #[test]
     fn drift_does_not_affect_energy_error() {
         let drift = make_drift("drift".to_string(), 2f64, 10f64);
@@ -161,6 +501,260 @@ mod tests {
         }
     }
 
+    #[test]
+    fn acccav_wake_only_acts_on_charged_beam() {
+        let details = AccCavDetails {
+            length: 3f64,
+            wavenumber: 0f64,
+            frequency: 3e9,
+            voltage: 0f64,
+            phase: 0f64,
+            phase_convention: PhaseConvention::Crest,
+            voltage_convention: VoltageConvention::Gradient,
+            wake: Some(Wake::SlacBane {
+                iris_radius: 11.6e-3,
+                gap: 29.2e-3,
+                period: 35e-3,
+            }),
+        };
+        let cavity = make_acccav("cavity".to_string(), details, GAMMA0);
+        let beam_vec = Array2::from(vec![[-1e-3, 0f64], [0f64, 0f64], [1e-3, 0f64]]);
+
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&cavity);
+        assert!(beam.pos.column(1).iter().all(|&delta| delta.abs() < 1e-12));
+
+        let mut beam = Beam::new(beam_vec);
+        beam.charge = 100e-12;
+        beam.track(&cavity);
+        assert!(beam.pos.column(1).iter().all(|&delta| delta < 0f64));
+        assert!(beam.pos[[0, 1]] < beam.pos[[2, 1]]);
+    }
+
+    #[test]
+    fn csr_only_acts_in_dipole_on_charged_beam() {
+        let mut dipole = make_dipole("dipole".to_string(), 0.5, 0.1, GAMMA0).unwrap();
+        dipole.ele_type = EleType::Dipole(DipoleDetails {
+            csr: Some(Default::default()),
+            ..Default::default()
+        });
+        let beam_vec = Array2::from(vec![[-1e-4, 0f64], [0f64, 0f64], [1e-4, 0f64]]);
+
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&dipole);
+        assert!(beam.pos.column(1).iter().all(|&delta| delta == 0f64));
+
+        let mut beam = Beam::new(beam_vec);
+        beam.charge = 100e-12;
+        beam.track(&dipole);
+        assert!(beam.pos.column(1).iter().any(|&delta| delta != 0f64));
+    }
+
+    #[test]
+    fn lsc_drift_only_acts_on_charged_beam() {
+        let lsc = Lsc {
+            radius: 1e-4,
+            bins: 50,
+        };
+        let drift = make_lsc_drift("lsc".to_string(), 1f64, GAMMA0, lsc);
+        let z = Array1::linspace(-5e-4, 5e-4, 201);
+        let mut beam_vec = Array2::zeros((z.len(), 2));
+        beam_vec.column_mut(0).assign(&z);
+
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&drift);
+        assert!(beam.pos.column(1).iter().all(|&delta| delta == 0f64));
+
+        let mut beam = Beam::new(beam_vec);
+        beam.weights = z.mapv(|z: f64| (-(z / 1e-4).powi(2) / 2f64).exp());
+        beam.charge = 100e-12;
+        beam.track(&drift);
+        assert!(beam.pos[[80, 1]] < 0f64);
+        assert!(beam.pos[[120, 1]] > 0f64);
+    }
+
+    #[test]
+    fn synchrotron_radiation_lowers_energy() {
+        let mut dipole = make_dipole("dipole".to_string(), 1f64, 0.1, GAMMA0).unwrap();
+        let beam_vec = Array2::from(vec![[0f64, -0.01], [0f64, 0f64], [0f64, 0.01]]);
+        dipole.ele_type = EleType::Dipole(DipoleDetails {
+            synch_rad: true,
+            ..Default::default()
+        });
+        assert!(dipole.design_energy_loss() > 0f64);
+
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&dipole);
+        // Delta is relative to the reference after the loss, and higher energies lose more
+        assert!(beam.pos[[1, 1]].abs() < 1e-12);
+        assert!(beam.pos[[0, 1]] > -0.01);
+        assert!(beam.pos[[2, 1]] < 0.01);
+
+        dipole.ele_type = EleType::Dipole(DipoleDetails {
+            synch_rad: true,
+            isr_seed: Some(1),
+            ..Default::default()
+        });
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&dipole);
+        let mut other_beam = Beam::new(beam_vec);
+        other_beam.track(&dipole);
+        assert!(beam.pos[[1, 1]] != 0f64);
+        assert_eq!(beam.pos, other_beam.pos);
+    }
+
+    #[test]
+    fn cavity_far_off_crest_stops_particles() {
+        let gamma = ke_2_gamma(10e6);
+        let details = AccCavDetails {
+            length: 1f64,
+            wavenumber: 0f64,
+            frequency: 3e9,
+            voltage: 50e6,
+            phase: PI / 2f64,
+            phase_convention: PhaseConvention::Crest,
+            voltage_convention: VoltageConvention::Gradient,
+            wake: None,
+        };
+        let cavity = make_acccav("cavity".to_string(), details, gamma);
+        let quarter_wavelength = C / 3e9 / 4f64;
+        let beam_vec = Array2::from(vec![
+            [-quarter_wavelength, 0f64],
+            [0f64, 0f64],
+            [quarter_wavelength, 0f64],
+        ]);
+        let mut beam = Beam::new(beam_vec);
+        beam.track(&cavity);
+        assert!(!beam.is_alive(0));
+        assert!(beam.is_alive(1) && beam.is_alive(2));
+        assert!(beam.pos[[2, 1]] > 0f64);
+    }
+
+    #[test]
+    fn deflector_only_streaks_the_beam() {
+        let details = DeflectorDetails {
+            length: 3f64,
+            wavenumber: 0f64,
+            frequency: 3e9,
+            voltage: 30e6,
+            phase: 0f64,
+            phase_convention: PhaseConvention::Elegant,
+        };
+        let deflector = make_deflector("tdc".to_string(), details, GAMMA0);
+        let drift = make_drift("drift".to_string(), 3f64, GAMMA0);
+        let beam_vec = Array2::from(vec![[-1e-4, 0.01], [0f64, 0f64], [1e-4, -0.01]]);
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&deflector);
+        let mut drifted = Beam::new(beam_vec);
+        drifted.track(&drift);
+        assert_eq!(beam.pos, drifted.pos);
+
+        let EleType::Deflector(details) = &deflector.ele_type else {
+            panic!("Expected a deflector");
+        };
+        let calibration = details.streak_calibration(GAMMA0, 10f64);
+        assert!(details.screen_position(0f64, GAMMA0, 10f64).abs() < 1e-12);
+        let z = 1e-6;
+        assert_ulps_eq!(
+            details.screen_position(z, GAMMA0, 10f64),
+            calibration * z,
+            epsilon = 1e-6 * (calibration * z).abs()
+        );
+    }
+
+    #[test]
+    fn collimator_removes_particles_outside_its_limits() {
+        let details = CollimatorDetails::from_aperture(-0.01, 0.02, -0.5);
+        assert_eq!(details.delta_min, -0.04);
+        assert_eq!(details.delta_max, 0.02);
+        let collimator = make_collimator("ecol".to_string(), 0.1, GAMMA0, details);
+        let drift = make_drift("drift".to_string(), 1f64, GAMMA0);
+        let beam_vec = Array2::from(vec![
+            [0f64, -0.05],
+            [0f64, 0f64],
+            [1e-3, 0.01],
+            [0f64, 0.03],
+        ]);
+        let mut beam = Beam::new(beam_vec);
+        beam.track(&drift);
+        beam.track(&collimator);
+        let row = |beam: &Beam, id: usize| beam.row_of(id).unwrap();
+        assert_eq!(
+            beam.lost[row(&beam, 0)],
+            Some(ParticleLoss {
+                element: 1,
+                name: "ecol".to_string()
+            })
+        );
+        assert!(beam.is_alive(row(&beam, 1)) && beam.is_alive(row(&beam, 2)));
+        assert!(!beam.is_alive(row(&beam, 3)));
+        assert_eq!(beam.transmission(), 0.5);
+
+        let lost_pos = beam.pos.row(row(&beam, 3)).to_owned();
+        beam.track(&drift);
+        assert_eq!(beam.pos.row(row(&beam, 3)), lost_pos);
+        assert_eq!(beam.stats().mean_delta, 0.005);
+        assert_eq!(beam.elements_tracked, 3);
+    }
+
+    #[test]
+    fn wiggler_adds_to_drift_r56() {
+        let k = 2f64;
+        let details = WigglerDetails {
+            k,
+            period: 0.05,
+            laser_heater: None,
+        };
+        let wiggler = make_wiggler("wiggler".to_string(), 1f64, GAMMA0, details);
+        let drift = make_drift("drift".to_string(), 1f64, GAMMA0);
+        let extra_r56 = wiggler.params["r56"] - drift.params["r56"];
+        assert_ulps_eq!(
+            extra_r56,
+            k.powi(2) / (2f64 * GAMMA0.powi(2)),
+            epsilon = 1e-15
+        );
+        assert_ulps_eq!(
+            WigglerDetails::k_from_field(1f64, 0.01),
+            0.9337,
+            epsilon = 1e-4
+        );
+    }
+
+    #[test]
+    fn laser_heater_modulates_energy() {
+        let heater = LaserHeater {
+            wavelength: 800e-9,
+            amplitude: 10e3,
+            phase_spread: 0f64,
+            seed: 1,
+        };
+        let details = WigglerDetails {
+            k: 1f64,
+            period: 0.05,
+            laser_heater: Some(heater.clone()),
+        };
+        let wiggler = make_wiggler("heater".to_string(), 0.5, GAMMA0, details);
+        let beam_vec = Array2::from(vec![[0f64, 0f64], [200e-9, 0f64], [600e-9, 0f64]]);
+        let mut beam = Beam::new(beam_vec.clone());
+        beam.track(&wiggler);
+        let max_delta = 10e3 / (GAMMA0 * MASS * gamma_2_beta(GAMMA0));
+        assert!(beam.pos[[0, 1]].abs() < 1e-12);
+        assert_ulps_eq!(beam.pos[[1, 1]], max_delta, epsilon = 1e-9);
+        assert_ulps_eq!(beam.pos[[2, 1]], -max_delta, epsilon = 1e-9);
+
+        let spread = LaserHeater {
+            phase_spread: 2f64 * PI,
+            ..heater
+        };
+        let z = Array1::zeros(1000);
+        let kicks = spread.energy_kicks(z.view());
+        let mean = kicks.iter().sum::<f64>() / 1000f64;
+        let rms = (kicks.iter().map(|k| k.powi(2)).sum::<f64>() / 1000f64).sqrt();
+        assert!(mean.abs() < 1e3);
+        assert!((rms - 10e3 / 2f64.sqrt()).abs() < 1e3);
+        assert_eq!(kicks, spread.energy_kicks(z.view()));
+    }
+
     #[test]
     fn quad_does_not_affect_energy_error() {
         let quad = make_quad("quad".to_string(), 2f64, 10f64);
@@ -0,0 +1,79 @@
+use crate::elegant_rpn::RpnError;
+use std::{fmt, io};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileLoc {
+    pub filename: String,
+    pub row: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for FileLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.filename, self.row, self.col)
+    }
+}
+
+/// Everything that can go wrong while reading an accelerator or beam description, or writing
+/// out the results.
+#[derive(Debug)]
+pub enum LotrError {
+    /// The file could not be read
+    Io { filename: String, source: io::Error },
+    /// The file could not be written
+    Write { filename: String, source: io::Error },
+    /// Unexpected or uninterpretable input at a location in a file
+    Syntax { loc: FileLoc, message: String },
+    /// An elegant RPN expression could not be evaluated
+    Rpn { loc: FileLoc, source: RpnError },
+    /// An element whose parameters make no physical sense
+    InvalidElement { name: String, message: String },
+    /// A line or element that is used but never defined
+    UndefinedName { name: String },
+    /// A data file whose contents do not match its own description
+    BadData { filename: String, message: String },
+    /// Tracking was asked to carry on past the last element
+    TrackedToEnd,
+}
+
+impl LotrError {
+    pub fn syntax(loc: &FileLoc, message: impl Into<String>) -> Self {
+        LotrError::Syntax {
+            loc: loc.clone(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LotrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LotrError::Io { filename, source } => {
+                write!(f, "Could not open file '{filename}': {source}")
+            }
+            LotrError::Write { filename, source } => {
+                write!(f, "Could not write file '{filename}': {source}")
+            }
+            LotrError::Syntax { loc, message } => write!(f, "{loc}: {message}"),
+            LotrError::Rpn { loc, source } => write!(f, "{loc}: {source}"),
+            LotrError::InvalidElement { name, message } => {
+                write!(f, "Element '{name}': {message}")
+            }
+            LotrError::UndefinedName { name } => {
+                write!(f, "'{name}' is used but cannot be found")
+            }
+            LotrError::BadData { filename, message } => write!(f, "{filename}: {message}"),
+            LotrError::TrackedToEnd => write!(f, "Have already tracked to the last element"),
+        }
+    }
+}
+
+impl std::error::Error for LotrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LotrError::Io { source, .. } | LotrError::Write { source, .. } => Some(source),
+            LotrError::Rpn { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
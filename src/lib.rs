@@ -0,0 +1,29 @@
+//! LOTRust: a LOngitudinal Tracker in Rust.
+//!
+//! Load an accelerator with [`load_lotr_file`], [`load_elegant_file`] or [`load_ele_file`], give the resulting
+//! [`Simulation`] an input [`Beam`], and [`Simulation::track`] it through the [`Element`]s.
+
+pub mod beam;
+pub mod csr;
+pub mod csv_beam;
+pub mod distribution;
+pub mod elegant_rpn;
+pub mod elements;
+pub mod error;
+pub mod lsc;
+mod namelist;
+pub mod parse_ele;
+pub mod parse_elegant;
+pub mod parse_lotr;
+pub mod random;
+pub mod sdds;
+pub mod wakefield;
+
+pub use beam::Beam;
+pub use elements::{EleType, Element};
+pub use error::LotrError;
+pub use parse_ele::load_ele_file;
+pub use parse_elegant::load_elegant_file;
+pub use parse_lotr::{
+    load_lotr_file, out_energyprofile, out_lossmap, out_statsprofile, Simulation,
+};
@@ -0,0 +1,135 @@
+use crate::beam::C;
+use crate::wakefield::Z0;
+use ndarray::ArrayView1;
+use std::f64::consts::PI;
+
+/// Number of slices used for space charge when the lattice does not give one
+pub const LSC_BINS: usize = 100;
+
+/// Longitudinal space charge of a round beam of uniform transverse density
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lsc {
+    /// Effective beam radius (m)
+    pub radius: f64,
+    /// Number of slices used to find the line density
+    pub bins: usize,
+}
+
+impl Lsc {
+    /// Impedance per unit length (Ohm/m) at wavenumber `k` (1/m) for a beam with `gamma`
+    pub fn impedance(&self, k: f64, gamma: f64) -> f64 {
+        let x = k * self.radius / gamma;
+        Z0 / (PI * k * self.radius.powi(2)) * one_minus_x_k1(x)
+    }
+
+    /// Energy change (eV) of each particle at position `z` caused by a bunch of total `charge`
+    /// (C), shared according to `weights`, drifting `length` metres with the reference `gamma`.
+    /// Particles with larger `z` are ahead.
+    pub fn energy_kicks(
+        &self,
+        z: ArrayView1<f64>,
+        weights: ArrayView1<f64>,
+        charge: f64,
+        gamma: f64,
+        length: f64,
+    ) -> Vec<f64> {
+        let num_particles = z.len();
+        let total_weight = weights.sum();
+        let bins = self.bins.max(1);
+        if num_particles == 0 || charge == 0f64 || total_weight == 0f64 || self.radius <= 0f64 {
+            return vec![0f64; num_particles];
+        }
+        let z_min = z.fold(f64::INFINITY, |a, &b| a.min(b));
+        let z_max = z.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let bin_width = (z_max - z_min) / bins as f64;
+        if bin_width == 0f64 {
+            return vec![0f64; num_particles];
+        }
+        let bin_of = |z: f64| (((z - z_min) / bin_width) as usize).min(bins - 1);
+        let centre = |bin: usize| (bin as f64 + 0.5) * bin_width;
+
+        let mut fraction = vec![0f64; bins];
+        for (&z_val, &weight) in z.iter().zip(weights) {
+            fraction[bin_of(z_val)] += weight / total_weight;
+        }
+
+        // The line density is expanded in a Fourier series over twice the bunch length, so that
+        // the head and tail do not see each other, and each harmonic is kicked by the impedance.
+        let period = 2f64 * bins as f64 * bin_width;
+        let mut kick_per_metre = vec![0f64; bins];
+        for harmonic in 1..=bins {
+            let k = 2f64 * PI * harmonic as f64 / period;
+            let (mut cos_sum, mut sin_sum) = (0f64, 0f64);
+            for (bin, frac) in fraction.iter().enumerate() {
+                cos_sum += frac * (k * centre(bin)).cos();
+                sin_sum += frac * (k * centre(bin)).sin();
+            }
+            let amplitude = 2f64 * charge * C * self.impedance(k, gamma) / period;
+            for (bin, kick) in kick_per_metre.iter_mut().enumerate() {
+                let phase = k * centre(bin);
+                *kick += amplitude * (phase.sin() * cos_sum - phase.cos() * sin_sum);
+            }
+        }
+
+        z.iter()
+            .map(|&z_val| length * kick_per_metre[bin_of(z_val)])
+            .collect()
+    }
+}
+
+/// 1 - x K1(x), where K1 is the modified Bessel function of the second kind, using the
+/// polynomial approximations of Abramowitz and Stegun (9.8.3, 9.8.7 and 9.8.8).
+fn one_minus_x_k1(x: f64) -> f64 {
+    if x <= 2f64 {
+        let t = (x / 3.75).powi(2);
+        let i1 = x
+            * (0.5
+                + t * (0.87890594
+                    + t * (0.51498869
+                        + t * (0.15084934
+                            + t * (0.02658733 + t * (0.00301532 + t * 0.00032411))))));
+        let y = (x / 2f64).powi(2);
+        -x * (x / 2f64).ln() * i1
+            - y * (0.15443144
+                + y * (-0.67278579
+                    + y * (-0.18156897 + y * (-0.01919402 + y * (-0.00110404 + y * -0.00004686)))))
+    } else {
+        let y = 2f64 / x;
+        let k1 = (-x).exp() / x.sqrt()
+            * (1.25331414
+                + y * (0.23498619
+                    + y * (-0.03655620
+                        + y * (0.01504268
+                            + y * (-0.00780353 + y * (0.00325614 + y * -0.00068245))))));
+        1f64 - x * k1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array1;
+
+    #[test]
+    fn bessel_approximations_meet() {
+        let below = one_minus_x_k1(2f64);
+        let above = one_minus_x_k1(2f64 + 1e-12);
+        assert!((below - above).abs() < 1e-6);
+        assert!(one_minus_x_k1(1e-6) > 0f64);
+        assert!((one_minus_x_k1(50f64) - 1f64).abs() < 1e-12);
+    }
+
+    #[test]
+    fn head_gains_and_tail_loses_energy() {
+        let z = Array1::linspace(-5e-4, 5e-4, 2001);
+        let weights = z.mapv(|z: f64| (-(z / 1e-4).powi(2) / 2f64).exp());
+        let lsc = Lsc {
+            radius: 1e-4,
+            bins: 100,
+        };
+        let kicks = lsc.energy_kicks(z.view(), weights.view(), 100e-12, 20f64, 1f64);
+        assert!(kicks[1200] > 0f64);
+        assert!(kicks[800] < 0f64);
+        assert!((kicks[1200] + kicks[800]).abs() < 0.1 * kicks[1200]);
+    }
+}
@@ -1,6 +1,13 @@
-use crate::beam::print_beam;
-use crate::parse_elegant::load_elegant_file;
-use crate::parse_lotr::{load_lotr_file, Simulation};
+use lotrust::beam::{
+    gamma_2_ke, print_beam, print_current_profile, print_stats, print_streaked_image,
+    print_transmission, CURRENT_PROFILE_BINS, CURRENT_PROFILE_SMOOTHING,
+};
+use lotrust::csv_beam::load_csv_beam;
+use lotrust::sdds::{beam_to_sdds, load_sdds_beam, write_sdds, SddsMode};
+use lotrust::{
+    load_ele_file, load_elegant_file, load_lotr_file, out_energyprofile, out_lossmap,
+    out_statsprofile, EleType, LotrError, Simulation,
+};
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result};
 use std::collections::VecDeque;
@@ -10,12 +17,6 @@ use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::{env, io};
 
-mod beam;
-mod elegant_rpn;
-mod elements;
-mod parse_elegant;
-mod parse_lotr;
-
 #[derive(Clone, PartialEq)]
 enum Token {
     Exit,
@@ -29,6 +30,10 @@ enum Token {
     Step,
     Reset,
     SetAccEnergy,
+    SetDispersion,
+    LaserHeater,
+    Set,
+    Streak,
     Help,
 }
 
@@ -46,22 +51,14 @@ struct Options {
 struct State {
     running: bool,
     simulation: Simulation,
+    /// Number of bins and half-width of the smoothing of the current profile
+    profile_bins: usize,
+    profile_smoothing: usize,
 }
 
 const HISTORYFILE: &str = "history";
 const CONFIGDIR: &str = ".config/LOTR/";
 
-pub fn out_energyprofile(sink: &mut impl Write, sim: &Simulation) {
-    let mut z = 0f64;
-    for (ind, ele) in sim.elements.iter().enumerate() {
-        if let Err(e) = writeln!(sink, "{}, {}, {}", ind, z, ele.gamma) {
-            println!("{}", e);
-            break;
-        }
-        z += ele.length;
-    }
-}
-
 fn lex(text: &str) -> Token {
     match text {
         "exit" | "quit" => Token::Exit,
@@ -74,6 +71,10 @@ fn lex(text: &str) -> Token {
         "step" => Token::Step,
         "reset" => Token::Reset,
         "set_acc_energy" => Token::SetAccEnergy,
+        "set_dispersion" => Token::SetDispersion,
+        "laser_heater" => Token::LaserHeater,
+        "set" => Token::Set,
+        "streak" => Token::Streak,
         "help" => Token::Help,
         _ => {
             println!("ERROR: Cannot understand token: {}", text);
@@ -93,7 +94,14 @@ fn parse_input(text: &str, mut state: State) -> State {
                 println!("track                   :: Track the beam through the accelerator, stopping at the");
                 println!("                           first breakpoint (if defined) or the end of the line.");
                 println!("load_lattice <filename> :: Load a new accelerator from 'filename'.");
-                println!("load_beam <filename>    :: Load a new input beam from 'filename'");
+                println!("load_beam <filename>    :: Load a new input beam from 'filename' (*.lotr, or an");
+                println!("                           elegant particle file if it ends in '.sdds', with z");
+                println!("                           measured from the mean arrival time)");
+                println!("load_beam <filename.csv> [<reference_ke>]");
+                println!("                        :: Load a new input beam from the columns 't' and 'p',");
+                println!("                           'z' and 'delta', or 'z' and 'dE' (eV) of a CSV file,");
+                println!("                           and rescale the accelerator to the reference KE (eV),");
+                println!("                           by default the mean KE of the particles.");
                 println!("break <element_name>    :: Add a breakpoint to the first element named 'element_name'");
                 println!("reset                   :: Remove all breakpoints, reset tracking status to the start");
                 println!(
@@ -102,22 +110,103 @@ fn parse_input(text: &str, mut state: State) -> State {
                 println!("save <param> <filename> :: Saves 'param' to a 'filename'. 'param' may be one of the following:");
                 println!("                                        * 'input_beam'");
                 println!("                                        * 'output_beam'");
+                println!(
+                    "                                          (as an elegant particle file if"
+                );
+                println!("                                          'filename' ends in '.sdds')");
                 println!("                                        * 'accelerator'");
                 println!("                                        * 'energy_profile'");
+                println!("                                        * 'stats_profile' (index, s, mean z, rms z,");
+                println!("                                          mean delta, rms delta, chirp, emittance");
+                println!("                                          after each tracked element)");
+                println!("                                        * 'current_profile' (z, current of the output beam)");
+                println!("                                        * 'loss_map' (index, s, particles lost, charge lost,");
+                println!("                                          transmission after each tracked element)");
                 println!("print <param>           :: Prints 'param' to the screen.  'param' may be one of");
                 println!(
-                    "                           those defined for the 'save' command (above)."
+                    "                           those defined for the 'save' command (above),"
+                );
+                println!(
+                    "                           or 'beam_stats' for statistics of the output beam,"
                 );
+                println!(
+                    "                           or 'transmission' for the charge left in the output beam"
+                );
+                println!("                           and the particles lost in each element.");
                 println!("set_acc_energy <energy> :: Sets the expected input KE of the accelerator to <energy>");
                 println!("                           recalculating the expected energy at each component");
                 println!("                           appropriately.");
                 println!("                        :: No scaling is done of the parameters of the component.");
                 println!("                        :: If <energy> is 'beam', then the KE of the input beam is used.");
+                println!("set_dispersion <element_name> <etax>");
+                println!("                        :: Sets the dispersion (m) at the elegant ECOL, RCOL or SCRAPER");
+                println!("                           'element_name', whose horizontal aperture then limits the");
+                println!(
+                    "                           energy deviation of the particles let through."
+                );
+                println!("laser_heater <element_name> <wavelength> <amplitude> <phase_spread>");
+                println!("                        :: Makes the wiggler 'element_name' a laser heater, modulating");
+                println!("                           the energy by 'amplitude' (eV) at the laser 'wavelength' (m)");
+                println!("                           with a random phase slippage spread over 'phase_spread' radians.");
+                println!("set <param> <value>     :: Sets a simulation option. 'param' may be one of the following:");
+                println!("                                        * 'tracking_order' (1, 2, 3 or 'exact'): the order of");
+                println!("                                          the path-length expansion in drifts and dipoles, or");
+                println!("                                          'exact' to use each particle's own velocity and");
+                println!(
+                    "                                          bending radius. Defaults to 1."
+                );
+                println!("                                        * 'random_seed': the seed of the random kicks of ISR");
+                println!("                                          and laser heaters, from which each element draws");
+                println!("                                          its own. Loading a lattice resets it to the");
+                println!("                                          lattice's own, by default 987654321.");
+                println!("                                        * 'current_profile_bins': the number of bins of the");
+                println!(
+                    "                                          current profile. Defaults to 100."
+                );
+                println!("                                        * 'current_profile_smoothing': the half-width, in bins,");
+                println!("                                          of the moving average that smooths the current");
+                println!("                                          profile, or 0 for none. Defaults to 1.");
+                println!("streak <element_name> <r34> <filename>");
+                println!("                        :: Saves the image (screen position, charge, id) of the beam,");
+                println!("                           streaked by the deflector 'element_name' onto a screen with");
+                println!("                           the given R34 (m/rad), to 'filename', and prints the streak");
+                println!("                           calibration and the bunch length measured from the image.");
             }
             Token::Error => break,
             Token::Exit => state.running = false,
-            Token::Track => state.simulation.track(),
-            Token::Step => state.simulation.step(),
+            Token::Track => {
+                let sim = &mut state.simulation;
+                match sim.track() {
+                    Ok(num_tracked) => println!(
+                        "Tracked {} particles through {} accelerator elements",
+                        sim.input_beam.pos.nrows(),
+                        num_tracked
+                    ),
+                    Err(e) => {
+                        print_tracking_error(&e);
+                        break;
+                    }
+                }
+                if let Some(element) = sim.elements.get(sim.current) {
+                    println!(
+                        "Stopping at element {} ({}) due to a breakpoint",
+                        sim.current, element.name
+                    );
+                }
+                print_history_saved(sim);
+            }
+            Token::Step => {
+                let sim = &mut state.simulation;
+                if let Err(e) = sim.step() {
+                    print_tracking_error(&e);
+                    break;
+                }
+                println!(
+                    "Stepped {} particles through a single element",
+                    sim.input_beam.pos.nrows()
+                );
+                print_history_saved(sim);
+            }
             Token::SetAccEnergy => {
                 if items.is_empty() {
                     println!(
@@ -139,6 +228,85 @@ fn parse_input(text: &str, mut state: State) -> State {
                     }
                 }
             }
+            Token::SetDispersion => {
+                if items.len() < 2 {
+                    items.clear();
+                    println!("ERROR: set_dispersion requires an element name and a dispersion.");
+                    println!("       set_dispersion <element_name> <etax>");
+                    break;
+                }
+                let name = items.pop_front().unwrap();
+                let val = items.pop_front().unwrap();
+                match val.parse::<f64>() {
+                    Ok(dispersion) => {
+                        if let Err(e) = state.simulation.set_dispersion(name, dispersion) {
+                            println!("ERROR: {e}");
+                        }
+                    }
+                    Err(_) => println!("ERROR: '{val}' could not be parsed as a float."),
+                }
+            }
+            Token::LaserHeater => {
+                if items.len() < 4 {
+                    items.clear();
+                    println!("ERROR: laser_heater requires a wiggler name and three values.");
+                    println!(
+                        "       laser_heater <element_name> <wavelength> <amplitude> <phase_spread>"
+                    );
+                    break;
+                }
+                let name = items.pop_front().unwrap();
+                let vals: Vec<&str> = items.drain(..3).collect();
+                match vals
+                    .iter()
+                    .map(|val| val.parse::<f64>())
+                    .collect::<std::result::Result<Vec<f64>, _>>()
+                {
+                    Ok(vals) => {
+                        if let Err(e) = state
+                            .simulation
+                            .set_laser_heater(name, vals[0], vals[1], vals[2])
+                        {
+                            println!("ERROR: {e}");
+                        }
+                    }
+                    Err(_) => {
+                        println!("ERROR: '{}' could not be parsed as floats.", vals.join(" "))
+                    }
+                }
+            }
+            Token::Set => {
+                if items.len() < 2 {
+                    items.clear();
+                    println!("ERROR: set requires a parameter name and a value.");
+                    println!("       set tracking_order <1|2|3|exact>");
+                    println!("       set random_seed <seed>");
+                    println!("       set current_profile_bins <bins>");
+                    println!("       set current_profile_smoothing <half_width>");
+                    break;
+                }
+                let param = items.pop_front().unwrap();
+                let val = items.pop_front().unwrap();
+                match param {
+                    "tracking_order" => match val.parse() {
+                        Ok(order) => state.simulation.tracking_order = order,
+                        Err(e) => println!("ERROR: {e}"),
+                    },
+                    "random_seed" => match val.parse() {
+                        Ok(seed) => state.simulation.set_random_seed(seed),
+                        Err(_) => println!("ERROR: '{val}' is not a whole number."),
+                    },
+                    "current_profile_bins" => match val.parse::<usize>() {
+                        Ok(bins) if bins > 0 => state.profile_bins = bins,
+                        _ => println!("ERROR: '{val}' is not a positive whole number of bins."),
+                    },
+                    "current_profile_smoothing" => match val.parse::<usize>() {
+                        Ok(smoothing) => state.profile_smoothing = smoothing,
+                        Err(_) => println!("ERROR: '{val}' is not a whole number of bins."),
+                    },
+                    _ => println!("ERROR: Cannot understand '{param}'"),
+                }
+            }
             Token::LoadLattice => {
                 if items.is_empty() {
                     println!("ERROR: Loading a lattice file requires specifying a filename.");
@@ -146,20 +314,30 @@ fn parse_input(text: &str, mut state: State) -> State {
                     break;
                 }
                 let filename = items.pop_front().unwrap();
-                let newsim: Simulation;
-                if filename.ends_with("lte") {
+                let newsim = if filename.ends_with(".ele") {
+                    load_ele_file(filename)
+                } else if filename.ends_with("lte") {
                     if items.is_empty() {
                         println!("ERROR: Loading an elegant file requires also specifying which line to use.");
                         println!("       load_lattice <elegantfilename> <elegant_line>");
                         break;
                     }
                     let elegant_line = items.pop_front().unwrap();
-                    newsim = load_elegant_file(filename, elegant_line);
-                    state.simulation.elements = newsim.elements;
+                    load_elegant_file(filename, Some(elegant_line))
                 } else {
-                    newsim = load_lotr_file(filename);
-                    state.simulation.elements = newsim.elements;
+                    load_lotr_file(filename)
                 };
+                match newsim {
+                    Ok(newsim) => {
+                        state.simulation.elements = newsim.elements;
+                        state.simulation.random_seed = newsim.random_seed;
+                        state.simulation.always_change_p0 = newsim.always_change_p0;
+                    }
+                    Err(e) => {
+                        println!("ERROR: {e}");
+                        break;
+                    }
+                }
             }
             Token::LoadBeam => {
                 if items.is_empty() {
@@ -168,8 +346,33 @@ fn parse_input(text: &str, mut state: State) -> State {
                     break;
                 }
                 let filename = items.pop_front().unwrap();
-                let newsim: Simulation = load_lotr_file(filename);
-                state.simulation.input_beam = newsim.input_beam;
+                if filename.ends_with(".csv") {
+                    let reference_ke = items.front().and_then(|item| item.parse::<f64>().ok());
+                    if reference_ke.is_some() {
+                        items.pop_front();
+                    }
+                    match load_csv_beam(filename, reference_ke) {
+                        Ok((beam, ke)) => state.simulation.set_input_beam(beam, ke),
+                        Err(e) => {
+                            println!("ERROR: {e}");
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                let loaded = if filename.ends_with(".sdds") {
+                    let gamma0 = state.simulation.input_beam_gamma();
+                    load_sdds_beam(filename, Some(gamma0), true).map(|(beam, _)| beam)
+                } else {
+                    load_lotr_file(filename).map(|newsim| newsim.input_beam)
+                };
+                match loaded {
+                    Ok(beam) => state.simulation.input_beam = beam,
+                    Err(e) => {
+                        println!("ERROR: {e}");
+                        break;
+                    }
+                }
             }
             Token::Print => {
                 if items.is_empty() {
@@ -177,18 +380,48 @@ fn parse_input(text: &str, mut state: State) -> State {
                     break;
                 }
                 let print_what = items.pop_front().unwrap();
-                match print_what {
+                let printed = match print_what {
                     "input_beam" => print_beam(&mut io::stdout(), &state.simulation.input_beam),
                     "output_beam" => print_beam(&mut io::stdout(), &state.simulation.output_beam),
                     "accelerator" => {
-                        if let Err(e) =
-                            writeln!(&mut io::stdout(), "{:?}", state.simulation.elements)
-                        {
-                            println!("Could not write to stdout...: {e}");
-                        }
+                        writeln!(&mut io::stdout(), "{:?}", state.simulation.elements)
                     }
                     "energy_profile" => out_energyprofile(&mut io::stdout(), &state.simulation),
-                    _ => println!("ERROR: Cannot understand '{print_what}'"),
+                    "stats_profile" | "loss_map" if state.simulation.stats_history.is_empty() => {
+                        println!("ERROR: The beam has not been tracked yet.");
+                        Ok(())
+                    }
+                    "stats_profile" => out_statsprofile(&mut io::stdout(), &state.simulation),
+                    "loss_map" => out_lossmap(&mut io::stdout(), &state.simulation),
+                    "beam_stats" => {
+                        print_stats(&mut io::stdout(), &state.simulation.output_beam.stats())
+                    }
+                    "current_profile" => print_current_profile(
+                        &mut io::stdout(),
+                        &state
+                            .simulation
+                            .output_beam
+                            .current_profile(state.profile_bins, state.profile_smoothing),
+                    ),
+                    "transmission" => {
+                        print_transmission(&mut io::stdout(), &state.simulation.output_beam)
+                    }
+                    "peak_current" => {
+                        let profile = state
+                            .simulation
+                            .output_beam
+                            .current_profile(state.profile_bins, state.profile_smoothing);
+                        println!("Peak current (A): {}", profile.peak_current());
+                        println!("FWHM (m)        : {}", profile.fwhm());
+                        Ok(())
+                    }
+                    _ => {
+                        println!("ERROR: Cannot understand '{print_what}'");
+                        Ok(())
+                    }
+                };
+                if let Err(e) = printed {
+                    println!("ERROR: Could not write to stdout: {e}");
                 }
             }
             Token::Save => {
@@ -197,38 +430,44 @@ fn parse_input(text: &str, mut state: State) -> State {
                         items.pop_front();
                     }
                     println!("ERROR: Expected additional input after the 'save' command");
-                    println!("       Either 'input_beam, 'output_beam', 'accelerator', or 'energy_profile',");
+                    println!("       Either 'input_beam, 'output_beam', 'accelerator', 'energy_profile',");
+                    println!("       'stats_profile', 'current_profile', or 'loss_map',");
                     println!("       and then the name of the file");
                     break;
                 }
                 let save_what = items.pop_front().unwrap();
                 let filename = items.pop_front().unwrap();
+                let sim = &state.simulation;
                 match save_what {
+                    "input_beam" | "output_beam" if filename.ends_with(".sdds") => {
+                        let (beam, gamma0) = if save_what == "input_beam" {
+                            (&sim.input_beam, sim.input_beam_gamma())
+                        } else {
+                            (&sim.output_beam, sim.output_beam_gamma())
+                        };
+                        let sdds = beam_to_sdds(beam, gamma0);
+                        save_to_file(filename, |file| write_sdds(file, &sdds, SddsMode::Binary));
+                    }
                     "input_beam" => {
-                        if let Ok(mut file) = File::create(filename) {
-                            print_beam(&mut file, &state.simulation.input_beam);
-                        }
+                        save_to_file(filename, |file| print_beam(file, &sim.input_beam))
                     }
                     "output_beam" => {
-                        if let Ok(mut file) = File::create(filename) {
-                            print_beam(&mut file, &state.simulation.output_beam);
-                        }
+                        save_to_file(filename, |file| print_beam(file, &sim.output_beam))
                     }
                     "accelerator" => {
-                        if let Ok(mut file) = File::create(filename) {
-                            if let Err(e) = writeln!(&mut file, "{:?}", state.simulation.elements) {
-                                println!("ERROR: Could not write the file: {e}");
-                            }
-                        } else {
-                            println!("ERROR: Could not write the file");
-                        }
+                        save_to_file(filename, |file| writeln!(file, "{:?}", sim.elements))
                     }
-                    "energy_profile" => {
-                        if let Ok(mut file) = File::create(filename) {
-                            out_energyprofile(&mut file, &state.simulation);
-                        } else {
-                            println!("ERROR: Could not write the file");
-                        }
+                    "energy_profile" => save_to_file(filename, |file| out_energyprofile(file, sim)),
+                    "stats_profile" | "loss_map" if sim.stats_history.is_empty() => {
+                        println!("ERROR: The beam has not been tracked yet.");
+                    }
+                    "stats_profile" => save_to_file(filename, |file| out_statsprofile(file, sim)),
+                    "loss_map" => save_to_file(filename, |file| out_lossmap(file, sim)),
+                    "current_profile" => {
+                        let profile = sim
+                            .output_beam
+                            .current_profile(state.profile_bins, state.profile_smoothing);
+                        save_to_file(filename, |file| print_current_profile(file, &profile));
                     }
                     _ => println!("ERROR: Cannot understand '{save_what}'"),
                 }
@@ -247,6 +486,40 @@ fn parse_input(text: &str, mut state: State) -> State {
                     );
                 }
             }
+            Token::Streak => {
+                if items.len() < 3 {
+                    items.clear();
+                    println!("ERROR: Expected 'streak <element_name> <r34> <filename>'");
+                    break;
+                }
+                let name = items.pop_front().unwrap().to_string();
+                let r34 = items.pop_front().unwrap();
+                let filename = items.pop_front().unwrap();
+                let r34: f64 = match r34.parse() {
+                    Ok(r34) => r34,
+                    Err(_) => {
+                        println!("ERROR: Cannot understand '{r34}' as an R34");
+                        break;
+                    }
+                };
+                let Some(index) = state.simulation.find_element_by_name(name.clone()) else {
+                    println!("ERROR: Cannot find an element named '{name}'");
+                    break;
+                };
+                let element = &state.simulation.elements[index];
+                let EleType::Deflector(details) = &element.ele_type else {
+                    println!("ERROR: '{name}' is not a deflector");
+                    break;
+                };
+                let image = state
+                    .simulation
+                    .beam_entering(index)
+                    .live_particles()
+                    .streaked_image(details, element.gamma, r34);
+                println!("Streak calibration (m/m): {}", image.calibration);
+                println!("rms bunch length (m)    : {}", image.rms_length());
+                save_to_file(filename, |file| print_streaked_image(file, &image));
+            }
             Token::Reset => {
                 state.simulation.breakpoints_passed = Vec::new();
                 state.simulation.breakpoints = Vec::new();
@@ -259,12 +532,39 @@ fn parse_input(text: &str, mut state: State) -> State {
     state
 }
 
+/// Creates `filename` and fills it with `write`, reporting any failure
+fn save_to_file(filename: &str, write: impl FnOnce(&mut File) -> io::Result<()>) {
+    if let Err(source) = File::create(filename).and_then(|mut file| write(&mut file)) {
+        let e = LotrError::Write {
+            filename: filename.to_string(),
+            source,
+        };
+        println!("ERROR: {e}");
+    }
+}
+
+fn print_tracking_error(e: &LotrError) {
+    println!("ERROR: {e}");
+    if matches!(e, LotrError::TrackedToEnd) {
+        println!("       Consider using `reset`.");
+    }
+}
+
+/// Reports the history being written, which happens once tracking reaches the end of the line
+fn print_history_saved(sim: &Simulation) {
+    if let Some(filename) = sim.save_filename.as_ref() {
+        if sim.current == sim.elements.len() {
+            println!("Phase space at each element saved to '{filename}'");
+        }
+    }
+}
+
 fn usage(program_name: String) {
     println!("{program_name} <input_file> [-e line_name] [-b <beam_defn_file>] [-s <output_file>]");
-    println!("\tinputfile: The file containing the description of the lattice");
+    println!("\tinputfile: The file containing the description of the lattice, or an elegant run file (*.ele)");
     println!("\t-e: Indicates that the input file is in elegant format. The name of the line to expand must be given");
-    println!("\t-b: Overrides any beam definition with that found in <beam_defn_file>");
-    println!("\t-s: Saves the output into <output_file>");
+    println!("\t-b: Overrides any beam definition with that found in <beam_defn_file> (*.lotr, *.sdds or *.csv)");
+    println!("\t-s: Saves the phase space after every element into <output_file> (numpy format)");
 }
 
 fn check_options(opts: &Options) -> bool {
@@ -351,17 +651,43 @@ fn main() -> Result<()> {
     }
 
     // TODO(#8): Should be able to read elegant lte files
-    let mut simulation: Simulation = if options.elegant {
-        load_elegant_file(&options.input_filename, &options.elegant_line)
+    let loaded = if options.input_filename.ends_with(".ele") {
+        load_ele_file(&options.input_filename)
+    } else if options.elegant {
+        load_elegant_file(&options.input_filename, Some(&options.elegant_line))
     } else {
         load_lotr_file(&options.input_filename)
     };
+    let mut simulation: Simulation = match loaded {
+        Ok(simulation) => simulation,
+        Err(e) => {
+            eprintln!("ERROR: {e}");
+            exit(1);
+        }
+    };
 
     if options.beam_defined {
-        let newsim: Simulation = load_lotr_file(&options.beam_filename);
-        simulation.input_beam = newsim.input_beam;
-        let new_ke = newsim.input_beam_ke;
-        simulation.rescale_acc_energy(new_ke);
+        let loaded = if options.beam_filename.ends_with(".csv") {
+            load_csv_beam(&options.beam_filename, None)
+        } else if options.beam_filename.ends_with(".sdds") {
+            load_sdds_beam(&options.beam_filename, None, true)
+                .map(|(beam, gamma0)| (beam, gamma_2_ke(gamma0)))
+        } else {
+            load_lotr_file(&options.beam_filename)
+                .map(|newsim| (newsim.input_beam, newsim.input_beam_ke))
+        };
+        let (beam, new_ke) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                exit(1);
+            }
+        };
+        simulation.set_input_beam(beam, new_ke);
+    }
+
+    if options.save_file {
+        simulation.save_filename = Some(options.save_filename);
     }
 
     simulation.output_beam = simulation.input_beam.clone();
@@ -369,6 +695,8 @@ fn main() -> Result<()> {
     let mut state = State {
         running: true,
         simulation,
+        profile_bins: CURRENT_PROFILE_BINS,
+        profile_smoothing: CURRENT_PROFILE_SMOOTHING,
     };
 
     println!("Welcome to LOTR! A Rust powered particle tracker.");
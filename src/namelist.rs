@@ -0,0 +1,186 @@
+use crate::error::{FileLoc, LotrError};
+
+/// A `&name key = value, ... &end` block, as used by elegant run files and SDDS headers
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Namelist {
+    pub name: String,
+    pub loc: FileLoc,
+    /// Keys are lowercase; values keep their case, without quotes, and the elements of an array
+    /// value are joined by commas
+    pub values: Vec<(String, String)>,
+}
+
+impl Namelist {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of `key` as a number, if it is given
+    pub fn get_f64(&self, key: &str) -> Result<Option<f64>, LotrError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => value.parse().map(Some).map_err(|_| {
+                LotrError::syntax(
+                    &self.loc,
+                    format!(
+                        "Expected a number for '{key}' in '&{}', got '{value}'",
+                        self.name
+                    ),
+                )
+            }),
+        }
+    }
+
+    /// The value of `key` as a flag, false if it is not given
+    pub fn get_flag(&self, key: &str) -> Result<bool, LotrError> {
+        Ok(self.get_f64(key)?.is_some_and(|value| value != 0f64))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Word {
+    Start(String),
+    Text(String),
+    Quoted(String),
+    Assign,
+}
+
+/// Splits `contents` into namelist words, skipping '!' comments, with the location of each
+fn split_words(contents: &str, loc: &FileLoc) -> Result<Vec<(Word, FileLoc)>, LotrError> {
+    let mut words = vec![];
+    let mut chars = contents.chars().peekable();
+    let mut loc = loc.clone();
+    while let Some(&chr) = chars.peek() {
+        let here = loc.clone();
+        if chr == '\n' {
+            chars.next();
+            loc.row += 1;
+            loc.col = 1;
+        } else if chr.is_whitespace() || chr == ',' {
+            chars.next();
+            loc.col += 1;
+        } else if chr == '!' {
+            while chars.next_if(|&c| c != '\n').is_some() {}
+        } else if chr == '=' {
+            chars.next();
+            loc.col += 1;
+            words.push((Word::Assign, here));
+        } else if chr == '"' {
+            chars.next();
+            loc.col += 1;
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek() == Some(&'"') => {
+                        text.push(chars.next().unwrap());
+                        loc.col += 1;
+                    }
+                    Some(c) => text.push(c),
+                    None => return Err(LotrError::syntax(&here, "Unterminated string")),
+                }
+                loc.col += 1;
+            }
+            loc.col += 1;
+            words.push((Word::Quoted(text), here));
+        } else {
+            let mut text = String::new();
+            while let Some(c) =
+                chars.next_if(|&c| !(c.is_whitespace() || c == ',' || c == '=' || c == '"'))
+            {
+                text.push(c);
+                loc.col += 1;
+            }
+            match text.strip_prefix('&') {
+                Some(name) => words.push((Word::Start(name.to_lowercase()), here)),
+                None => words.push((Word::Text(text), here)),
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// Reads every namelist in `contents`, which starts at `loc` in its file
+pub(crate) fn parse_namelists(contents: &str, loc: &FileLoc) -> Result<Vec<Namelist>, LotrError> {
+    let words = split_words(contents, loc)?;
+    let mut namelists: Vec<Namelist> = vec![];
+    let mut current: Option<Namelist> = None;
+    let mut ind = 0;
+    while ind < words.len() {
+        let (word, loc) = &words[ind];
+        ind += 1;
+        match (word, current.as_mut()) {
+            (Word::Start(name), None) if name != "end" => {
+                current = Some(Namelist {
+                    name: name.clone(),
+                    loc: loc.clone(),
+                    values: vec![],
+                });
+            }
+            (Word::Start(name), Some(_)) if name == "end" => {
+                namelists.extend(current.take());
+            }
+            (Word::Text(key), Some(namelist))
+                if matches!(words.get(ind), Some((Word::Assign, _))) =>
+            {
+                ind += 1;
+                let mut values: Vec<String> = vec![];
+                while let Some((Word::Text(value) | Word::Quoted(value), _)) = words.get(ind) {
+                    if matches!(words.get(ind + 1), Some((Word::Assign, _))) {
+                        break;
+                    }
+                    values.push(value.clone());
+                    ind += 1;
+                }
+                namelist.values.push((key.to_lowercase(), values.join(",")));
+            }
+            _ => {
+                let found = match word {
+                    Word::Start(name) => format!("&{name}"),
+                    Word::Text(text) | Word::Quoted(text) => text.clone(),
+                    Word::Assign => "=".to_string(),
+                };
+                return Err(LotrError::syntax(
+                    loc,
+                    format!("Unexpected '{found}' in a namelist"),
+                ));
+            }
+        }
+    }
+    if let Some(namelist) = current {
+        return Err(LotrError::syntax(
+            &namelist.loc,
+            format!("'&{}' has no '&end'", namelist.name),
+        ));
+    }
+    Ok(namelists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_values_and_arrays_are_read() {
+        let loc = FileLoc {
+            filename: "test.ele".to_string(),
+            row: 1,
+            col: 1,
+        };
+        let contents = "&run_setup ! comment\n  lattice = Test.lte,\n  output = \"out/%s.out\"\n  \
+                        p_central = 4.9e2 values = 1, 2, 3\n&end\n&track &end\n";
+        let namelists = parse_namelists(contents, &loc).unwrap();
+        assert_eq!(namelists.len(), 2);
+        assert_eq!(namelists[0].name, "run_setup");
+        assert_eq!(namelists[0].get("lattice"), Some("Test.lte"));
+        assert_eq!(namelists[0].get("output"), Some("out/%s.out"));
+        assert_eq!(namelists[0].get_f64("p_central").unwrap(), Some(490f64));
+        assert_eq!(namelists[0].get("values"), Some("1,2,3"));
+        assert_eq!(namelists[1].name, "track");
+        assert!(namelists[1].values.is_empty());
+        assert!(parse_namelists("&run_setup lattice = a.lte", &loc).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::beam::{gamma_2_ke, MASS};
+use crate::error::{FileLoc, LotrError};
+use crate::namelist::{parse_namelists, Namelist};
+use crate::parse_elegant::load_elegant_file;
+use crate::parse_lotr::Simulation;
+use crate::sdds::load_sdds_beam;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Loads an elegant run file, setting up the simulation it describes: the beamline
+/// `use_beamline` (or the last line defined) of the `lattice` named in `&run_setup`, with the
+/// reference momentum `p_central` (or `p_central_mev`), tracking the beam read from the `input`
+/// of `&sdds_beam`. Without a reference momentum, the mean momentum of that beam is used.
+///
+/// With `always_change_p0`, the reference momentum is moved to the mean momentum of the beam
+/// after each element, rather than following the design energy gain of each cavity.
+/// The random kicks of the lattice are seeded by `random_number_seed`, if it is given.
+/// Relative paths are taken from the directory of the run file.
+pub fn load_ele_file(filename: &str) -> Result<Simulation, LotrError> {
+    let contents = read_to_string(filename).map_err(|source| LotrError::Io {
+        filename: filename.to_string(),
+        source,
+    })?;
+    let start = FileLoc {
+        filename: filename.to_string(),
+        row: 1,
+        col: 1,
+    };
+    let namelists = parse_namelists(&contents, &start)?;
+    let find = |name: &str| namelists.iter().find(|namelist| namelist.name == name);
+    let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+    let relative_path = |path: &str| directory.join(path).to_string_lossy().into_owned();
+
+    let run_setup = find("run_setup").ok_or_else(|| {
+        LotrError::syntax(&start, "An elegant run file needs a '&run_setup' namelist")
+    })?;
+    let lattice = required(run_setup, "lattice")?;
+    let beamline = run_setup.get("use_beamline");
+    let p_central = match run_setup.get_f64("p_central_mev")? {
+        Some(p_mev) => Some(p_mev * 1e6 / MASS),
+        None => run_setup.get_f64("p_central")?,
+    };
+
+    let mut sim = load_elegant_file(&relative_path(lattice), beamline)?;
+    sim.always_change_p0 = run_setup.get_flag("always_change_p0")?;
+    if let Some(seed) = run_setup.get_f64("random_number_seed")? {
+        sim.set_random_seed(seed as u64);
+    }
+    let design_gamma = p_central.map(|p| (1f64 + p * p).sqrt());
+    if let Some(sdds_beam) = find("sdds_beam") {
+        let input = required(sdds_beam, "input")?;
+        let center_arrival_time = sdds_beam.get_flag("center_arrival_time")?;
+        let (beam, gamma0) =
+            load_sdds_beam(&relative_path(input), design_gamma, center_arrival_time)?;
+        sim.input_beam = beam;
+        sim.input_beam_ke = gamma_2_ke(gamma0);
+    } else if let Some(gamma0) = design_gamma {
+        sim.input_beam_ke = gamma_2_ke(gamma0);
+    }
+    let ke = sim.input_beam_ke;
+    sim.rescale_acc_energy(ke);
+    sim.output_beam = sim.input_beam.clone();
+    Ok(sim)
+}
+
+fn required<'a>(namelist: &'a Namelist, key: &str) -> Result<&'a str, LotrError> {
+    namelist.get(key).ok_or_else(|| {
+        LotrError::syntax(
+            &namelist.loc,
+            format!("'&{}' needs a value for '{key}'", namelist.name),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beam::{delta_2_gamma, ke_2_gamma};
+    use crate::sdds::read_sdds;
+    use std::f64::consts::PI;
+
+    const P_CENTRAL: f64 = 4.90236775979206e2;
+
+    #[test]
+    fn run_file_sets_up_lattice_beam_and_energy() {
+        let mut sim = load_ele_file("tests/elegant_test_runs/test_DRIFT.ele").unwrap();
+        assert_eq!(sim.elements.len(), 1);
+        assert_eq!(sim.elements[0].name, "test_drift");
+        assert_eq!(sim.input_beam.pos.nrows(), 34);
+        let gamma0 = (1f64 + P_CENTRAL.powi(2)).sqrt();
+        assert!((sim.elements[0].gamma - gamma0).abs() < 1e-12 * gamma0);
+        assert!((ke_2_gamma(sim.input_beam_ke) - gamma0).abs() < 1e-12 * gamma0);
+
+        // A drift leaves the energy of every particle alone, but always_change_p0 moves the
+        // reference to the mean energy of the beam
+        assert!(sim.always_change_p0);
+        sim.track().unwrap();
+        let mean_gamma = delta_2_gamma(sim.input_beam.stats().mean_delta, gamma0);
+        assert!((sim.output_beam_gamma() - mean_gamma).abs() < 1e-12 * mean_gamma);
+        assert!(sim.output_beam.stats().mean_delta.abs() < 1e-12);
+        let input = sim.input_beam.pos_by_id();
+        let output = sim.output_beam.pos_by_id();
+        for (before, after) in input.outer_iter().zip(output.outer_iter()) {
+            let gamma_before = delta_2_gamma(before[1], gamma0);
+            let gamma_after = delta_2_gamma(after[1], mean_gamma);
+            assert!((gamma_before - gamma_after).abs() < 1e-12 * gamma0);
+            assert!((before[0] - after[0]).abs() < 1e-6);
+        }
+    }
+
+    /// Loads test_DRIFT.ele with `edit` made to it, from a copy in the temp dir
+    fn load_edited_drift_run(name: &str, edit: impl Fn(String) -> String) -> Simulation {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/elegant_test_runs");
+        let contents = read_to_string(format!("{directory}/test_DRIFT.ele")).unwrap();
+        let contents = edit(contents)
+            .replace("test_lines.lte", &format!("{directory}/test_lines.lte"))
+            .replace("./input/", &format!("{directory}/input/"));
+        let run_file =
+            std::env::temp_dir().join(format!("lotrust_{name}_{}.ele", std::process::id()));
+        std::fs::write(&run_file, contents).unwrap();
+        let loaded = load_ele_file(&run_file.to_string_lossy());
+        std::fs::remove_file(&run_file).unwrap();
+        loaded.unwrap()
+    }
+
+    #[test]
+    fn run_file_without_always_change_p0_keeps_the_design_energy() {
+        let mut sim = load_edited_drift_run("fixed_p0", |contents| {
+            contents.replace("always_change_p0 = 1", "always_change_p0 = 0")
+        });
+        assert!(!sim.always_change_p0);
+        sim.track().unwrap();
+        assert_eq!(sim.output_beam_gamma(), sim.elements[0].gamma);
+        let input = sim.input_beam.pos_by_id();
+        let output = sim.output_beam.pos_by_id();
+        for (before, after) in input.outer_iter().zip(output.outer_iter()) {
+            assert!((before[1] - after[1]).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn run_file_uses_the_last_line_without_use_beamline() {
+        let sim = load_edited_drift_run("last_line", |contents| {
+            contents.replace("use_beamline = DRIFT,", "")
+        });
+        // MONI: line = ( "W-INIT", test_moni , "W-END" ), the watch points being left out
+        assert_eq!(sim.elements.len(), 1);
+        assert_eq!(sim.elements[0].name, "test_moni");
+    }
+
+    #[test]
+    fn cavity_gain_matches_elegant() {
+        let mut sim = load_ele_file("tests/elegant_test_runs/test_RFCW_CREST.ele").unwrap();
+        let gamma0 = (1f64 + P_CENTRAL.powi(2)).sqrt();
+        assert!((sim.elements[0].gamma - gamma0).abs() < 1e-12 * gamma0);
+        // volt = "rf_voltage 2 sqrt /", with rf_voltage = 6.44e5 156 *
+        let voltage = 6.44e5 * 156f64 / 2f64.sqrt();
+        sim.track().unwrap();
+
+        // elegant gives each particle V sin(phase + omega (t - t_mean)), on crest at 90 degrees
+        let sdds = read_sdds("tests/elegant_test_runs/input/test_beam.sdds").unwrap();
+        let t = sdds.column(0, "t").unwrap();
+        let p = sdds.column(0, "p").unwrap();
+        let t_mean = t.iter().sum::<f64>() / t.len() as f64;
+        let omega = 2f64 * PI * 2998e6;
+        let output = sim.output_beam.pos_by_id();
+        let n_particles = t.len() as f64;
+        let mut mean_gamma = 0f64;
+        for ((t, p), particle) in t.iter().zip(p).zip(output.outer_iter()) {
+            let gain = voltage * (PI / 2f64 + omega * (t - t_mean)).sin();
+            let expected = (1f64 + p * p).sqrt() + gain / MASS;
+            let gamma = delta_2_gamma(particle[1], sim.output_beam_gamma());
+            assert!((gamma - expected).abs() < 1e-6 * expected);
+            mean_gamma += expected / n_particles;
+        }
+        // always_change_p0 leaves the reference at the mean energy of the beam
+        let output_gamma = sim.output_beam_gamma();
+        assert!((output_gamma - mean_gamma).abs() < 1e-6 * mean_gamma);
+    }
+
+    #[test]
+    fn missing_run_file_is_an_error() {
+        assert!(matches!(
+            load_ele_file("tests/elegant_test_runs/not_a_run.ele"),
+            Err(LotrError::Io { .. })
+        ));
+    }
+}
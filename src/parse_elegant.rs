@@ -1,12 +1,21 @@
 use crate::beam::{ke_2_gamma, Beam, C, MASS};
+use crate::csr::{Csr, CSR_BINS, CSR_SMOOTHING};
 use crate::elegant_rpn::RpnCalculator;
-use crate::elements::{make_acccav, make_dipole, make_drift, make_quad, AccCavDetails};
+use crate::elements::{
+    make_acccav, make_collimator, make_deflector, make_dipole, make_drift, make_lsc_drift,
+    make_quad, make_wiggler, AccCavDetails, CollimatorDetails, DeflectorDetails, DipoleDetails,
+    EleType, PhaseConvention, VoltageConvention, WigglerDetails,
+};
+use crate::error::{FileLoc, LotrError};
+use crate::lsc::{Lsc, LSC_BINS};
 use crate::parse_lotr::Simulation;
+use crate::random::{element_seed, DEFAULT_SEED};
+use crate::wakefield::{load_wake_table, Wake};
 use core::f64::consts::PI;
 use ndarray::Array2;
 use std::collections::HashMap;
 use std::fs::read_to_string;
-use std::process::exit;
+use std::path::Path;
 use std::{f64, fmt};
 
 type Line = Vec<ElegantElement>;
@@ -15,7 +24,9 @@ type Line = Vec<ElegantElement>;
 struct Library {
     elements: HashMap<String, ElegantElement>,
     ignored: Vec<String>,
-    lines: HashMap<String, Vec<String>>,
+    lines: HashMap<String, Vec<LineItem>>,
+    /// Name of the line defined last
+    last_line: Option<String>,
 }
 
 impl Library {
@@ -27,7 +38,8 @@ impl Library {
         self.ignored.push(name);
     }
 
-    fn add_line(&mut self, name: String, elements: Vec<String>) {
+    fn add_line(&mut self, name: String, elements: Vec<LineItem>) {
+        self.last_line = Some(name.clone());
         self.lines.insert(name, elements);
     }
 }
@@ -37,21 +49,42 @@ struct ElegantElement {
     name: String,
     intermed_type: IntermedType,
     params: HashMap<String, f64>,
+    str_params: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
 enum IntermedType {
     Drift,
+    LscDrift,
     AccCav,
+    Deflector,
+    Collimator,
+    MomentumFilter,
+    Wiggler,
     Kick,
     Moni,
     Bend,
+    CsrBend,
     Quad,
     Sext,
-    Line(Vec<String>),
+    Line(Vec<LineItem>),
     Ignore,
 }
 
+/// One entry in a line definition, such as `CELL`, `-ARC`, `3*CELL` or `2*(A,B)`
+#[derive(Debug, Clone)]
+struct LineItem {
+    member: LineMember,
+    repeat: usize,
+    reversed: bool,
+}
+
+#[derive(Debug, Clone)]
+enum LineMember {
+    Name(String),
+    Group(Vec<LineItem>),
+}
+
 impl ElegantElement {
     fn get_param_or_default(&self, param: &str, default_val: f64) -> f64 {
         match self.params.get(param) {
@@ -61,13 +94,6 @@ impl ElegantElement {
     }
 }
 
-#[derive(Debug, Clone)]
-struct FileLoc {
-    filename: String,
-    row: usize,
-    col: usize,
-}
-
 #[derive(Debug, PartialEq, Clone)]
 enum TokenType {
     Word,
@@ -77,6 +103,7 @@ enum TokenType {
     Oparen,
     Cparen,
     Comma,
+    Star,
     Assign,
     Colon,
     EleStr,
@@ -99,30 +126,52 @@ struct Token {
     loc: FileLoc,
 }
 
-pub fn load_elegant_file(filename: &str, line_to_expand: &str) -> Simulation {
-    let line_to_expand = line_to_expand.to_lowercase();
+/// Loads the line `line_to_expand` of an elegant lattice file, or the last line defined in it
+/// if no line is given, as elegant does without a `use_beamline`
+pub fn load_elegant_file(
+    filename: &str,
+    line_to_expand: Option<&str>,
+) -> Result<Simulation, LotrError> {
     let mut calc: RpnCalculator = Default::default();
     let mut line: Line = vec![];
-    let tokens = tokenize_file(filename);
-    let inter_repr = parse_tokens(&tokens, &mut calc);
-    intermed_to_line(&mut line, &inter_repr, &line_to_expand);
+    let tokens = tokenize_file(filename)?;
+    let inter_repr = parse_tokens(&tokens, &mut calc)?;
+    let line_to_expand = match line_to_expand {
+        Some(name) => name.to_lowercase(),
+        None => inter_repr
+            .last_line
+            .clone()
+            .ok_or_else(|| LotrError::BadData {
+                filename: filename.to_string(),
+                message: "No beamline is defined".to_string(),
+            })?,
+    };
+    if !inter_repr.lines.contains_key(&line_to_expand) {
+        return Err(LotrError::UndefinedName {
+            name: line_to_expand,
+        });
+    }
+    intermed_to_line(&mut line, &inter_repr, &line_to_expand)?;
     line_to_simulation(line)
 }
 
-fn parse_string(input: &mut String, loc: FileLoc) -> Token {
+fn parse_string(input: &mut String, loc: FileLoc) -> Result<Token, LotrError> {
     let mut name: String = chop_character(input).to_string();
     while !input.is_empty() {
         name.push(chop_character(input));
-        if input.starts_with(|c: char| c == '"') {
+        if input.starts_with('"') {
             break;
         }
     }
+    if input.is_empty() {
+        return Err(LotrError::syntax(&loc, "Unterminated string"));
+    }
     name.push(chop_character(input));
-    Token {
+    Ok(Token {
         token_type: TokenType::EleStr,
         value: name,
         loc,
-    }
+    })
 }
 
 fn parse_rpn_expr(input: &mut String, mut loc: FileLoc) -> Token {
@@ -132,13 +181,15 @@ fn parse_rpn_expr(input: &mut String, mut loc: FileLoc) -> Token {
     }
     let mut name: String = chop_character(input).to_string();
     while !input.is_empty() {
-        if input.starts_with(|c: char| c == '\n') {
+        if input.starts_with('\n') {
             break;
         } else {
             name.push(chop_character(input));
         }
     }
-    chop_character(input);
+    if !input.is_empty() {
+        chop_character(input);
+    }
     Token {
         token_type: TokenType::RpnExpr,
         value: name,
@@ -176,13 +227,14 @@ fn chop_character(input: &mut String) -> char {
     input.remove(0)
 }
 
-fn tokenize_file(filename: &str) -> Vec<Token> {
+fn tokenize_file(filename: &str) -> Result<Vec<Token>, LotrError> {
     let mut contents = match read_to_string(filename) {
         Ok(contents) => contents.to_lowercase(),
-        Err(e) => {
-            eprintln!("{}", e);
-            eprintln!("Could not open file: '{}'", filename);
-            exit(1);
+        Err(source) => {
+            return Err(LotrError::Io {
+                filename: filename.to_string(),
+                source,
+            })
         }
     };
     let mut tokens: Vec<Token> = vec![];
@@ -214,8 +266,8 @@ fn tokenize_file(filename: &str) -> Vec<Token> {
                     col += 1;
                 }
             }
-        } else if contents.starts_with(|c: char| c == '"') {
-            let tok = parse_string(&mut contents, location);
+        } else if contents.starts_with('"') {
+            let tok = parse_string(&mut contents, location)?;
             col += tok.value.len();
             tokens.push(tok);
         } else if contents.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '-') {
@@ -254,6 +306,14 @@ fn tokenize_file(filename: &str) -> Vec<Token> {
                 loc: location,
             });
             col += 1;
+        } else if contents.starts_with('*') {
+            chop_character(&mut contents);
+            tokens.push(Token {
+                token_type: TokenType::Star,
+                value: "*".to_string(),
+                loc: location,
+            });
+            col += 1;
         } else if contents.starts_with('=') {
             chop_character(&mut contents);
             tokens.push(Token {
@@ -293,10 +353,7 @@ fn tokenize_file(filename: &str) -> Vec<Token> {
             row += 1;
             tokens.push(tok);
         } else if contents.starts_with('!') {
-            let mut next = chop_character(&mut contents);
-            while next != '\n' {
-                next = chop_character(&mut contents);
-            }
+            while !contents.is_empty() && chop_character(&mut contents) != '\n' {}
             if !tokens.is_empty()
                 && (tokens.last().unwrap().token_type != TokenType::LineJoin
                     && tokens.last().unwrap().token_type != TokenType::LineEnd)
@@ -311,7 +368,10 @@ fn tokenize_file(filename: &str) -> Vec<Token> {
             col = 1;
         } else {
             let chr = chop_character(&mut contents);
-            eprintln!("{}:{}:{} Unknown character '{}'", filename, row, col, chr,);
+            return Err(LotrError::syntax(
+                &location,
+                format!("Unknown character '{chr}'"),
+            ));
         }
     }
 
@@ -342,24 +402,31 @@ fn tokenize_file(filename: &str) -> Vec<Token> {
         cleaned_tokens.push(this_tok.clone());
     }
 
-    cleaned_tokens
+    Ok(cleaned_tokens)
 }
 
 fn get_tokens_for_next_ele(token_list: &[Token], ind: &mut usize) -> Vec<Token> {
     let mut return_token_list: Vec<Token> = vec![];
 
-    while token_list[*ind].token_type != TokenType::LineEnd {
+    while token_list[*ind].token_type != TokenType::LineEnd
+        && token_list[*ind].token_type != TokenType::Eof
+    {
         return_token_list.push(token_list[*ind].clone());
         *ind += 1;
     }
-    return_token_list.push(token_list[*ind].clone());
+    return_token_list.push(Token {
+        token_type: TokenType::LineEnd,
+        ..token_list[*ind].clone()
+    });
 
     return_token_list
 }
 
-fn get_param_list(token_list: &[Token], calc: &mut RpnCalculator) -> HashMap<String, f64> {
+fn get_param_list(
+    token_list: &[Token],
+    calc: &mut RpnCalculator,
+) -> Result<HashMap<String, f64>, LotrError> {
     let params_to_ignore = vec![
-        "zwake",
         "trwake",
         "zwakefile",
         "tcolumn",
@@ -379,20 +446,64 @@ fn get_param_list(token_list: &[Token], calc: &mut RpnCalculator) -> HashMap<Str
                 ind += 1;
                 continue;
             }
-            let param = token_list[ind].clone();
-            let value = token_list[ind + 2].clone();
-            assert!(param.token_type == TokenType::Word);
-            assert!(token_list[ind + 1].token_type == TokenType::Assign);
-            assert!(value.token_type == TokenType::Value || value.token_type == TokenType::EleStr);
+            let param = &token_list[ind];
+            if param.token_type != TokenType::Word {
+                return Err(LotrError::syntax(
+                    &param.loc,
+                    format!("Expected a parameter name, but got '{}'", param.value),
+                ));
+            }
+            match token_list.get(ind + 1) {
+                Some(tok) if tok.token_type == TokenType::Assign => {}
+                _ => {
+                    return Err(LotrError::syntax(
+                        &param.loc,
+                        format!("Expected '=' after '{}'", param.value),
+                    ))
+                }
+            }
+            let value = match token_list.get(ind + 2) {
+                Some(tok)
+                    if tok.token_type == TokenType::Value
+                        || tok.token_type == TokenType::EleStr =>
+                {
+                    tok
+                }
+                _ => {
+                    return Err(LotrError::syntax(
+                        &param.loc,
+                        format!("Expected a value for '{}'", param.value),
+                    ))
+                }
+            };
 
             if !params_to_ignore.contains(&param.value.as_str()) {
-                let name_of_param = param.value;
+                let name_of_param = param.value.clone();
                 if value.token_type == TokenType::Value {
-                    params.insert(name_of_param, value.value.parse().unwrap());
-                } else {
-                    let store_key = value.value.clone().replace('"', "");
-                    let val = calc.interpret_string(&store_key).unwrap();
+                    let val = value.value.parse().map_err(|e| {
+                        LotrError::syntax(
+                            &value.loc,
+                            format!("Could not parse '{}': {e}", value.value),
+                        )
+                    })?;
                     params.insert(name_of_param, val);
+                } else {
+                    let store_key = value.value.replace('"', "");
+                    match calc.interpret_string(&store_key) {
+                        Ok(Some(val)) => params.insert(name_of_param, val),
+                        Ok(None) => {
+                            return Err(LotrError::syntax(
+                                &value.loc,
+                                format!("'{store_key}' does not evaluate to a value"),
+                            ))
+                        }
+                        Err(source) => {
+                            return Err(LotrError::Rpn {
+                                loc: value.loc.clone(),
+                                source,
+                            })
+                        }
+                    };
                 }
             }
             ind += 3;
@@ -401,139 +512,293 @@ fn get_param_list(token_list: &[Token], calc: &mut RpnCalculator) -> HashMap<Str
     if !params.contains_key("l") {
         params.insert("l".to_string(), 0f64);
     }
+    Ok(params)
+}
+
+/// Collects the parameters that name files or columns rather than holding a value.
+/// Relative file names are taken to be relative to the lattice file.
+fn get_str_param_list(token_list: &[Token]) -> HashMap<String, String> {
+    let str_params = ["zwakefile", "tcolumn", "wzcolumn", "insert_from"];
+    let mut params = HashMap::<String, String>::new();
+    for window in token_list.windows(3) {
+        if window[0].token_type == TokenType::Word
+            && window[1].token_type == TokenType::Assign
+            && window[2].token_type == TokenType::EleStr
+            && str_params.contains(&window[0].value.as_str())
+        {
+            let mut value = window[2].value.replace('"', "");
+            if window[0].value.ends_with("file") && Path::new(&value).is_relative() {
+                if let Some(dir) = Path::new(&window[2].loc.filename).parent() {
+                    value = dir.join(value).to_string_lossy().to_string();
+                }
+            }
+            params.insert(window[0].value.clone(), value);
+        }
+    }
     params
 }
 
-fn get_next_ele_from_tokens(token_list: &[Token], calc: &mut RpnCalculator) -> ElegantElement {
-    assert!(
-        token_list[0].token_type == TokenType::Word
-            || token_list[0].token_type == TokenType::EleStr
-    );
-    assert!(token_list[1].token_type == TokenType::Colon);
-    assert!(token_list[2].token_type == TokenType::Word);
+fn get_next_ele_from_tokens(
+    token_list: &[Token],
+    calc: &mut RpnCalculator,
+) -> Result<ElegantElement, LotrError> {
+    let ele_type = match token_list.get(2) {
+        Some(tok) if tok.token_type == TokenType::Word => tok,
+        _ => {
+            return Err(LotrError::syntax(
+                &token_list[0].loc,
+                format!("Expected an element type after '{}:'", token_list[0].value),
+            ))
+        }
+    };
 
     let ele_name = token_list[0].value.replace('"', "");
 
-    match token_list[2].value.as_str() {
+    let ele = match ele_type.value.as_str() {
         "charge" | "magnify" | "malign" | "watch" | "watchpoint" | "mark" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Ignore,
             params: HashMap::<String, f64>::new(),
+            str_params: HashMap::new(),
         },
-        "drift" | "marker" | "scraper" | "ecol" | "wiggler" => ElegantElement {
+        "drift" | "marker" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Drift,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
+        },
+        "wiggler" => ElegantElement {
+            name: ele_name,
+            intermed_type: IntermedType::Wiggler,
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
+        },
+        "ecol" | "rcol" | "scraper" => ElegantElement {
+            name: ele_name,
+            intermed_type: IntermedType::Collimator,
+            params: get_param_list(token_list, calc)?,
+            str_params: get_str_param_list(token_list),
         },
-        "rfcw" | "rfdf" => ElegantElement {
+        "pfilter" => ElegantElement {
+            name: ele_name,
+            intermed_type: IntermedType::MomentumFilter,
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
+        },
+        "lscdrift" => ElegantElement {
+            name: ele_name,
+            intermed_type: IntermedType::LscDrift,
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
+        },
+        "rfcw" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::AccCav,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: get_str_param_list(token_list),
+        },
+        "rfdf" => ElegantElement {
+            name: ele_name,
+            intermed_type: IntermedType::Deflector,
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
         },
         "kquad" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Quad,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
         },
         "hkick" | "vkick" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Kick,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
         },
         "monitor" | "moni" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Moni,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
         },
-        "csrcsbend" | "rben" | "sben" | "sbend" => ElegantElement {
+        "csrcsbend" => ElegantElement {
+            name: ele_name,
+            intermed_type: IntermedType::CsrBend,
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
+        },
+        "rben" | "sben" | "sbend" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Bend,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
         },
         "ksext" => ElegantElement {
             name: ele_name,
             intermed_type: IntermedType::Sext,
-            params: get_param_list(token_list, calc),
+            params: get_param_list(token_list, calc)?,
+            str_params: HashMap::new(),
         },
         "line" => {
-            assert!(token_list[4].token_type == TokenType::Oparen);
-            let mut ind = 5;
-            let mut contained: Vec<String> = vec![];
-            while token_list[ind].token_type != TokenType::Cparen {
-                if token_list[ind].token_type == TokenType::Comma {
-                    ind += 1;
-                    continue;
-                }
-                let subline_name = token_list[ind].clone();
-                assert!(
-                    subline_name.token_type == TokenType::Word
-                        || subline_name.token_type == TokenType::EleStr,
-                    "Expected a 'Word', but got {:#?}",
-                    subline_name
-                );
-                if subline_name.token_type == TokenType::EleStr {
-                    contained.push(subline_name.value.replace('"', ""));
+            match token_list.get(4) {
+                Some(tok) if tok.token_type == TokenType::Oparen => {}
+                _ => {
+                    return Err(LotrError::syntax(
+                        &ele_type.loc,
+                        "Expected '(' to start the line definition",
+                    ))
                 }
-                contained.push(subline_name.value);
-                ind += 1;
             }
+            let mut ind = 5;
+            let contained = parse_line_items(token_list, &mut ind)?;
             ind += 1;
-            assert!(token_list[ind].token_type == TokenType::LineEnd);
+            if token_list[ind].token_type != TokenType::LineEnd {
+                return Err(LotrError::syntax(
+                    &token_list[ind].loc,
+                    format!(
+                        "Expected the end of the line, but got '{}'",
+                        token_list[ind].value
+                    ),
+                ));
+            }
             ElegantElement {
                 name: ele_name,
                 intermed_type: IntermedType::Line(contained),
                 params: HashMap::<String, f64>::new(),
+                str_params: HashMap::new(),
             }
         }
-        _ => {
-            eprintln!("Tokens to get ele from: {:#?}", token_list);
-            eprintln!("Exiting as unable to interpret the above.");
-            exit(1);
+        other => {
+            return Err(LotrError::syntax(
+                &ele_type.loc,
+                format!("Unable to interpret element type '{other}'"),
+            ))
+        }
+    };
+    Ok(ele)
+}
+
+/// Parse the items of a line definition up to the closing parenthesis, leaving `ind` on it
+fn parse_line_items(token_list: &[Token], ind: &mut usize) -> Result<Vec<LineItem>, LotrError> {
+    let mut items: Vec<LineItem> = vec![];
+    loop {
+        let tok = &token_list[*ind];
+        match tok.token_type {
+            TokenType::Cparen => return Ok(items),
+            TokenType::Comma => *ind += 1,
+            TokenType::LineEnd | TokenType::Eof => {
+                return Err(LotrError::syntax(&tok.loc, "Expected ')' to end the line"))
+            }
+            _ => items.push(parse_line_item(token_list, ind)?),
         }
     }
 }
 
+/// Parse one, possibly reversed and repeated, item of a line definition
+fn parse_line_item(token_list: &[Token], ind: &mut usize) -> Result<LineItem, LotrError> {
+    let mut reversed = false;
+    let mut repeat = 1;
+    let mut tok = &token_list[*ind];
+    if tok.token_type == TokenType::Value
+        && compare_tokentype_at(token_list, *ind + 1, TokenType::Star)
+    {
+        let count: f64 = tok.value.parse().unwrap();
+        if count.fract() != 0f64 {
+            return Err(LotrError::syntax(
+                &tok.loc,
+                format!(
+                    "Expected a whole number of repeats, but got '{}'",
+                    tok.value
+                ),
+            ));
+        }
+        reversed = count < 0f64;
+        repeat = count.abs() as usize;
+        *ind += 2;
+        tok = &token_list[*ind];
+    }
+
+    // The lexer keeps a leading '-' as part of the following word
+    let mut name = tok.value.clone();
+    if tok.token_type == TokenType::Word && name.starts_with('-') {
+        reversed = !reversed;
+        name.remove(0);
+    }
+
+    let member = if tok.token_type == TokenType::Oparen
+        || (tok.token_type == TokenType::Word
+            && name.is_empty()
+            && compare_tokentype_at(token_list, *ind + 1, TokenType::Oparen))
+    {
+        if tok.token_type == TokenType::Word {
+            *ind += 1;
+        }
+        *ind += 1;
+        let group = parse_line_items(token_list, ind)?;
+        LineMember::Group(group)
+    } else if (tok.token_type == TokenType::Word && !name.is_empty())
+        || tok.token_type == TokenType::EleStr
+    {
+        LineMember::Name(name.replace('"', ""))
+    } else {
+        return Err(LotrError::syntax(
+            &tok.loc,
+            format!("Expected a 'Word', but got '{}'", tok.value),
+        ));
+    };
+    *ind += 1;
+
+    Ok(LineItem {
+        member,
+        repeat,
+        reversed,
+    })
+}
+
 fn add_ele_to_store(
     token_list: &[Token],
     ind: &mut usize,
     store: &mut Library,
     calc: &mut RpnCalculator,
-) {
+) -> Result<(), LotrError> {
     use IntermedType::*;
     let toks = get_tokens_for_next_ele(token_list, ind);
-    let new_ele = get_next_ele_from_tokens(&toks, calc);
+    let new_ele = get_next_ele_from_tokens(&toks, calc)?;
     match new_ele.intermed_type {
         Ignore => store.ignore(new_ele.name),
-        Drift | AccCav | Quad | Kick | Moni | Bend | Sext => {
-            store.add_element(new_ele.name.clone(), new_ele)
-        }
+        Drift | LscDrift | AccCav | Deflector | Collimator | MomentumFilter | Wiggler | Quad
+        | Kick | Moni | Bend | CsrBend | Sext => store.add_element(new_ele.name.clone(), new_ele),
         Line(contents) => store.add_line(new_ele.name, contents),
     }
+    Ok(())
 }
 
-fn parse_tokens(token_list: &[Token], calc: &mut RpnCalculator) -> Library {
+fn parse_tokens(token_list: &[Token], calc: &mut RpnCalculator) -> Result<Library, LotrError> {
     use TokenType::*;
     let mut element_store: Library = Default::default();
     let mut ind: usize = 0;
     while ind < token_list.len() {
         let tok = &token_list[ind];
         if tok.token_type == RpnExpr {
-            calc.interpret_string(&tok.value);
+            calc.interpret_string(&tok.value)
+                .map_err(|source| LotrError::Rpn {
+                    loc: tok.loc.clone(),
+                    source,
+                })?;
         } else if (tok.token_type == Word || tok.token_type == EleStr)
             && compare_tokentype_at(token_list, ind + 1, Colon)
         {
-            add_ele_to_store(token_list, &mut ind, &mut element_store, calc);
+            add_ele_to_store(token_list, &mut ind, &mut element_store, calc)?;
         } else if tok.token_type == Eof {
             break;
         } else {
-            println!("{tok:?}", tok = token_list[ind - 1]);
-            println!("{tok:?}");
-            println!("{tok:?}", tok = token_list[ind + 1]);
-            eprintln!(
-                "{}:{}:{} Cannot handle '{}' with value '{}'",
-                tok.loc.filename, tok.loc.row, tok.loc.col, tok.token_type, tok.value
-            );
-            exit(1);
+            return Err(LotrError::syntax(
+                &tok.loc,
+                format!(
+                    "Cannot handle '{}' with value '{}'",
+                    tok.token_type, tok.value
+                ),
+            ));
         }
         ind += 1;
         if ind >= token_list.len() {
@@ -543,30 +808,58 @@ fn parse_tokens(token_list: &[Token], calc: &mut RpnCalculator) -> Library {
             ind += 1;
         }
     }
-    element_store
+    Ok(element_store)
 }
 
-fn intermed_to_line(line: &mut Line, intermed: &Library, line_name: &str) {
-    let line_name = &line_name.replace('"', "");
-    if let Some(line_defn) = intermed.lines.get(line_name) {
-        for subline in line_defn {
-            intermed_to_line(line, intermed, subline);
-        }
-    } else if intermed.ignored.contains(&line_name.to_string()) {
-    } else if let Some(ele) = intermed.elements.get(line_name) {
+fn intermed_to_line(line: &mut Line, intermed: &Library, line_name: &str) -> Result<(), LotrError> {
+    expand_name(line, intermed, line_name, false)
+}
+
+fn expand_name(
+    line: &mut Line,
+    intermed: &Library,
+    name: &str,
+    reversed: bool,
+) -> Result<(), LotrError> {
+    let name = &name.replace('"', "");
+    if let Some(line_defn) = intermed.lines.get(name) {
+        expand_items(line, intermed, line_defn, reversed)?;
+    } else if intermed.ignored.contains(&name.to_string()) {
+    } else if let Some(ele) = intermed.elements.get(name) {
         line.push(ele.clone());
     } else {
-        println!("{:#?}", intermed.ignored);
-        println!("{:#?}", intermed.lines.keys());
-        println!("{:#?}", intermed.ignored.contains(&line_name.to_string()));
-        eprintln!(
-            "Trying to expand the line called '{:#?}' but it cannot be found",
-            line_name
-        );
+        return Err(LotrError::UndefinedName {
+            name: name.to_string(),
+        });
     }
+    Ok(())
 }
 
-fn line_to_simulation(line: Line) -> Simulation {
+/// Reversing a line reverses the order of its items, and each sub-line in turn
+fn expand_items(
+    line: &mut Line,
+    intermed: &Library,
+    items: &[LineItem],
+    reversed: bool,
+) -> Result<(), LotrError> {
+    let ordered: Vec<&LineItem> = if reversed {
+        items.iter().rev().collect()
+    } else {
+        items.iter().collect()
+    };
+    for item in ordered {
+        let item_reversed = reversed != item.reversed;
+        for _ in 0..item.repeat {
+            match &item.member {
+                LineMember::Name(name) => expand_name(line, intermed, name, item_reversed)?,
+                LineMember::Group(group) => expand_items(line, intermed, group, item_reversed)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn line_to_simulation(line: Line) -> Result<Simulation, LotrError> {
     let input_beam = Beam::new(Array2::from(vec![[0f64, 0f64]]));
     let output_beam = Beam::new(Array2::from(vec![[0f64, 0f64]]));
     let mut acc = Simulation {
@@ -577,8 +870,16 @@ fn line_to_simulation(line: Line) -> Simulation {
         breakpoints: Vec::new(),
         breakpoints_passed: Vec::new(),
         current: 0,
+        history: Vec::new(),
+        stats_history: Vec::new(),
+        save_filename: None,
+        tracking_order: Default::default(),
+        random_seed: DEFAULT_SEED,
+        always_change_p0: false,
+        output_gamma: None,
     };
     let mut design_gamma = ke_2_gamma(acc.input_beam_ke);
+    let mut wake_cache: HashMap<String, Wake> = HashMap::new();
     for ele in line {
         match ele.intermed_type {
             IntermedType::Drift | IntermedType::Kick | IntermedType::Moni | IntermedType::Sext => {
@@ -586,13 +887,35 @@ fn line_to_simulation(line: Line) -> Simulation {
                 acc.elements
                     .push(make_drift(ele.name.to_string(), l, design_gamma))
             }
+            IntermedType::LscDrift => {
+                let l = ele.get_param_or_default("l", 0f64);
+                if ele.get_param_or_default("lsc", 1f64) == 0f64 {
+                    acc.elements
+                        .push(make_drift(ele.name.to_string(), l, design_gamma));
+                    continue;
+                }
+                let radius = ele.get_param_or_default("radius", 0f64);
+                if radius <= 0f64 {
+                    return Err(LotrError::InvalidElement {
+                        name: ele.name,
+                        message: "LSCDRIFT needs a positive RADIUS".to_string(),
+                    });
+                }
+                let bins = ele.get_param_or_default("bins", 0f64) as usize;
+                let lsc = Lsc {
+                    radius,
+                    bins: if bins > 0 { bins } else { LSC_BINS },
+                };
+                acc.elements
+                    .push(make_lsc_drift(ele.name.to_string(), l, design_gamma, lsc));
+            }
             IntermedType::Quad => {
                 let l = ele.get_param_or_default("l", 0f64);
                 acc.elements
                     .push(make_quad(ele.name.to_string(), l, design_gamma))
             }
             IntermedType::AccCav => {
-                let phase = ele.get_param_or_default("phase", 0f64).to_radians() - PI / 2f64;
+                let phase = ele.get_param_or_default("phase", 0f64);
                 let volt = if ele.params.contains_key("volt") {
                     ele.params["volt"]
                 } else if ele.params.contains_key("voltage") {
@@ -605,39 +928,190 @@ fn line_to_simulation(line: Line) -> Simulation {
                 } else if ele.params.contains_key("frequency") {
                     ele.params["frequency"]
                 } else {
-                    panic!("Param map in ele doesn't contain necessary freq/frequency key");
+                    return Err(LotrError::InvalidElement {
+                        name: ele.name,
+                        message: "Param map in ele doesn't contain necessary freq/frequency key"
+                            .to_string(),
+                    });
+                };
+                let wake = match ele.str_params.get("zwakefile") {
+                    Some(wakefile) if ele.get_param_or_default("zwake", 0f64) != 0f64 => {
+                        if !wake_cache.contains_key(wakefile) {
+                            let t_column = ele.str_params.get("tcolumn").map_or("t", |s| s);
+                            let w_column = ele.str_params.get("wzcolumn").map_or("W", |s| s);
+                            let wake = load_wake_table(wakefile, t_column, w_column)?;
+                            wake_cache.insert(wakefile.clone(), wake);
+                        }
+                        Some(wake_cache[wakefile].clone())
+                    }
+                    _ => None,
                 };
                 let k = 2f64 * PI * freq / C;
                 let details = AccCavDetails {
                     frequency: freq,
                     voltage: volt,
                     phase,
+                    phase_convention: PhaseConvention::Elegant,
+                    voltage_convention: VoltageConvention::Total,
                     length: ele.params["l"],
                     wavenumber: k,
+                    wake,
                 };
+                let energy_gain = details.design_energy_gain();
                 acc.elements
                     .push(make_acccav(ele.name.to_string(), details, design_gamma));
-                design_gamma += (volt * phase.cos()) / MASS;
+                design_gamma += energy_gain / MASS;
+                if design_gamma <= 1f64 {
+                    return Err(LotrError::InvalidElement {
+                        name: ele.name,
+                        message:
+                            "The reference particle has no kinetic energy left after this cavity"
+                                .to_string(),
+                    });
+                }
                 // println!("Calling make_lotr_element in line_to_simulation");
                 // if let Some(lotr_ele) = ele.make_lotr_element(&mut design_gamma) {
                 //     acc.elements.push(lotr_ele);
                 // };
             }
-            IntermedType::Bend => {
+            IntermedType::Wiggler => {
                 let l = ele.get_param_or_default("l", 0f64);
-                let angle = ele.get_param_or_default("angle", 0f64);
+                let poles = ele.get_param_or_default("poles", 0f64);
+                let period = if poles > 0f64 { 2f64 * l / poles } else { 0f64 };
+                let k = match (ele.params.get("k"), ele.params.get("b")) {
+                    (Some(&k), _) if k != 0f64 => k,
+                    (_, Some(&b_field)) if b_field != 0f64 => {
+                        if period == 0f64 {
+                            return Err(LotrError::InvalidElement {
+                                name: ele.name,
+                                message: "A WIGGLER given by B needs POLES to find its period"
+                                    .to_string(),
+                            });
+                        }
+                        WigglerDetails::k_from_field(b_field, period)
+                    }
+                    _ => 0f64,
+                };
+                // A laser heater is added with Simulation::set_laser_heater
+                let details = WigglerDetails {
+                    k,
+                    period,
+                    laser_heater: None,
+                };
+                acc.elements
+                    .push(make_wiggler(ele.name.to_string(), l, design_gamma, details));
+            }
+            IntermedType::Collimator => {
+                let l = ele.get_param_or_default("l", 0f64);
+                // The horizontal aperture only limits the energy deviation once the dispersion at
+                // the element is given, with Simulation::set_dispersion
+                let aperture = match ele.str_params.get("insert_from") {
+                    Some(side) => {
+                        let position = ele.get_param_or_default("position", 0f64);
+                        match side.to_lowercase().as_str() {
+                            "+x" | "x" => Some((f64::NEG_INFINITY, position)),
+                            "-x" => Some((position, f64::INFINITY)),
+                            _ => None,
+                        }
+                    }
+                    None => {
+                        let x_max = ele.get_param_or_default("x_max", 0f64);
+                        let dx = ele.get_param_or_default("dx", 0f64);
+                        (x_max > 0f64).then_some((dx - x_max, dx + x_max))
+                    }
+                };
+                let details = CollimatorDetails {
+                    aperture,
+                    ..Default::default()
+                };
+                acc.elements.push(make_collimator(
+                    ele.name.to_string(),
+                    l,
+                    design_gamma,
+                    details,
+                ));
+            }
+            IntermedType::MomentumFilter => {
+                // As in elegant, a negative DELTALIMIT lets everything through
+                let delta_limit = ele.get_param_or_default("deltalimit", -1f64);
+                let details = if delta_limit >= 0f64 {
+                    CollimatorDetails {
+                        delta_min: -delta_limit,
+                        delta_max: delta_limit,
+                        aperture: None,
+                    }
+                } else {
+                    Default::default()
+                };
+                acc.elements.push(make_collimator(
+                    ele.name.to_string(),
+                    0f64,
+                    design_gamma,
+                    details,
+                ));
+            }
+            IntermedType::Deflector => {
+                let frequency = match ele.params.get("frequency") {
+                    Some(&frequency) => frequency,
+                    None => {
+                        return Err(LotrError::InvalidElement {
+                            name: ele.name,
+                            message: "RFDF needs a FREQUENCY".to_string(),
+                        })
+                    }
+                };
+                let details = DeflectorDetails {
+                    length: ele.get_param_or_default("l", 0f64),
+                    wavenumber: 2f64 * PI * frequency / C,
+                    frequency,
+                    voltage: ele.get_param_or_default("voltage", 0f64),
+                    phase: ele.get_param_or_default("phase", 0f64),
+                    phase_convention: PhaseConvention::Elegant,
+                };
                 acc.elements
-                    .push(make_dipole(ele.name.to_string(), l, angle, design_gamma));
+                    .push(make_deflector(ele.name.to_string(), details, design_gamma));
             }
-            IntermedType::Line(_) => {
-                todo!()
+            IntermedType::Bend | IntermedType::CsrBend => {
+                let l = ele.get_param_or_default("l", 0f64);
+                let angle = ele.get_param_or_default("angle", 0f64);
+                let mut dipole = make_dipole(ele.name.to_string(), l, angle, design_gamma)?;
+                // As in elegant, CSR is on in a CSRCSBEND unless the element turns it off, and
+                // ISR needs SYNCH_RAD
+                let csr = if matches!(ele.intermed_type, IntermedType::CsrBend)
+                    && ele.get_param_or_default("csr", 1f64) != 0f64
+                {
+                    let bins = ele.get_param_or_default("bins", 0f64) as usize;
+                    Some(Csr {
+                        bins: if bins > 0 { bins } else { CSR_BINS },
+                        smoothing: ele.get_param_or_default("sg_halfwidth", CSR_SMOOTHING as f64)
+                            as usize,
+                    })
+                } else {
+                    None
+                };
+                let synch_rad = ele.get_param_or_default("synch_rad", 0f64) != 0f64;
+                let isr_seed = if synch_rad && ele.get_param_or_default("isr", 0f64) != 0f64 {
+                    Some(element_seed(acc.random_seed, acc.elements.len()))
+                } else {
+                    None
+                };
+                dipole.ele_type = EleType::Dipole(DipoleDetails {
+                    csr,
+                    synch_rad,
+                    isr_seed,
+                });
+                design_gamma -= dipole.design_energy_loss() / MASS;
+                acc.elements.push(dipole);
             }
-            IntermedType::Ignore => {
-                todo!()
+            IntermedType::Line(_) | IntermedType::Ignore => {
+                return Err(LotrError::InvalidElement {
+                    name: ele.name,
+                    message: "Lines and ignored elements cannot be tracked".to_string(),
+                })
             }
         }
     }
-    acc
+    Ok(acc)
 }
 
 fn compare_tokentype_at(token_list: &[Token], ind: usize, tok_type: TokenType) -> bool {
@@ -646,14 +1120,20 @@ fn compare_tokentype_at(token_list: &[Token], ind: usize, tok_type: TokenType) -
 
 #[cfg(test)]
 mod tests {
-    use crate::out_energyprofile;
+    use crate::{out_energyprofile, out_lossmap};
     use std::fs::File;
     use std::io::Read;
+    use std::path::PathBuf;
 
     use crate::{
         beam::print_beam,
+        csr::Csr,
+        elements::{DipoleDetails, EleType, LaserHeater, WigglerDetails},
+        error::LotrError,
+        lsc::Lsc,
         parse_elegant::load_elegant_file,
         parse_lotr::{load_lotr_file, Simulation},
+        random::{element_seed, DEFAULT_SEED},
     };
 
     pub fn diff_files(f1: &mut File, f2: &mut File) -> bool {
@@ -681,243 +1161,369 @@ mod tests {
         }
     }
 
+    /// Where a test writes the output it compares with a `_true` file: the temp dir, so that
+    /// running the tests leaves the tree alone
+    fn test_output(filename: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lotrust_{filename}"))
+    }
+
     const SPF_TESTFILE: &str = "tests/elegant_example.lte";
     const ELEGANT_TESTFILE: &str = "tests/test_lines.lte";
     const BEAM_TESTFILE: &str = "tests/test_beam.lotr";
 
     const DRIFT_BEAM_TRUE: &str = "tests/drift_output_true.beam";
-    const DRIFT_BEAM_TEST: &str = "tests/drift_output_test.beam";
+    const DRIFT_BEAM_TEST: &str = "drift_output_test.beam";
 
     const MARKER_BEAM_TRUE: &str = "tests/marker_output_true.beam";
-    const MARKER_BEAM_TEST: &str = "tests/marker_output_test.beam";
+    const MARKER_BEAM_TEST: &str = "marker_output_test.beam";
 
     const SBEND_BEAM_TRUE: &str = "tests/sbend_output_true.beam";
-    const SBEND_BEAM_TEST: &str = "tests/sbend_output_test.beam";
+    const SBEND_BEAM_TEST: &str = "sbend_output_test.beam";
 
     const HKICK_BEAM_TRUE: &str = "tests/hkick_output_true.beam";
-    const HKICK_BEAM_TEST: &str = "tests/hkick_output_test.beam";
+    const HKICK_BEAM_TEST: &str = "hkick_output_test.beam";
 
     const VKICK_BEAM_TRUE: &str = "tests/vkick_output_true.beam";
-    const VKICK_BEAM_TEST: &str = "tests/vkick_output_test.beam";
+    const VKICK_BEAM_TEST: &str = "vkick_output_test.beam";
 
     const KQUAD_BEAM_TRUE: &str = "tests/kquad_output_true.beam";
-    const KQUAD_BEAM_TEST: &str = "tests/kquad_output_test.beam";
+    const KQUAD_BEAM_TEST: &str = "kquad_output_test.beam";
 
     const RFCW_ZEROCROSSING_BEAM_TRUE: &str = "tests/rfcw_zerocrossing_output_true.beam";
-    const RFCW_ZEROCROSSING_BEAM_TEST: &str = "tests/rfcw_zerocrossing_output_test.beam";
+    const RFCW_ZEROCROSSING_BEAM_TEST: &str = "rfcw_zerocrossing_output_test.beam";
 
     const RFCW_CREST_BEAM_TRUE: &str = "tests/rfcw_crest_output_true.beam";
-    const RFCW_CREST_BEAM_TEST: &str = "tests/rfcw_crest_output_test.beam";
+    const RFCW_CREST_BEAM_TEST: &str = "rfcw_crest_output_test.beam";
 
     const RFDF_BEAM_TRUE: &str = "tests/rfdf_output_true.beam";
-    const RFDF_BEAM_TEST: &str = "tests/rfdf_output_test.beam";
+    const RFDF_BEAM_TEST: &str = "rfdf_output_test.beam";
 
     const WIGGLER_BEAM_TRUE: &str = "tests/wiggler_output_true.beam";
-    const WIGGLER_BEAM_TEST: &str = "tests/wiggler_output_test.beam";
+    const WIGGLER_BEAM_TEST: &str = "wiggler_output_test.beam";
 
     const CSRCSBEND_BEAM_TRUE: &str = "tests/csrcsbend_output_true.beam";
-    const CSRCSBEND_BEAM_TEST: &str = "tests/csrcsbend_output_test.beam";
+    const CSRCSBEND_BEAM_TEST: &str = "csrcsbend_output_test.beam";
 
     const RBEN_BEAM_TRUE: &str = "tests/rben_output_true.beam";
-    const RBEN_BEAM_TEST: &str = "tests/rben_output_test.beam";
+    const RBEN_BEAM_TEST: &str = "rben_output_test.beam";
 
     const SBEN_BEAM_TRUE: &str = "tests/sben_output_true.beam";
-    const SBEN_BEAM_TEST: &str = "tests/sben_output_test.beam";
+    const SBEN_BEAM_TEST: &str = "sben_output_test.beam";
 
     const KSEXT_BEAM_TRUE: &str = "tests/ksext_output_true.beam";
-    const KSEXT_BEAM_TEST: &str = "tests/ksext_output_test.beam";
+    const KSEXT_BEAM_TEST: &str = "ksext_output_test.beam";
 
     const SCRAPER_BEAM_TRUE: &str = "tests/scraper_output_true.beam";
-    const SCRAPER_BEAM_TEST: &str = "tests/scraper_output_test.beam";
+    const SCRAPER_BEAM_TEST: &str = "scraper_output_test.beam";
 
     const ECOL_BEAM_TRUE: &str = "tests/ecol_output_true.beam";
-    const ECOL_BEAM_TEST: &str = "tests/ecol_output_test.beam";
+    const ECOL_BEAM_TEST: &str = "ecol_output_test.beam";
 
     const MONITOR_BEAM_TRUE: &str = "tests/monitor_output_true.beam";
-    const MONITOR_BEAM_TEST: &str = "tests/monitor_output_test.beam";
+    const MONITOR_BEAM_TEST: &str = "monitor_output_test.beam";
 
     const MONI_BEAM_TRUE: &str = "tests/moni_output_true.beam";
-    const MONI_BEAM_TEST: &str = "tests/moni_output_test.beam";
+    const MONI_BEAM_TEST: &str = "moni_output_test.beam";
 
     const SPF_BEAM_TRUE: &str = "tests/spf_output_true.beam";
-    const SPF_BEAM_TEST: &str = "tests/spf_output_test.beam";
+    const SPF_BEAM_TEST: &str = "spf_output_test.beam";
 
     const SPF_ENERGY_PROFILE_TRUE: &str = "tests/spf_energy_profile_true.data";
-    const SPF_ENERGY_PROFILE_TEST: &str = "tests/spf_energy_profile_test.data";
+    const SPF_ENERGY_PROFILE_TEST: &str = "spf_energy_profile_test.data";
+
+    #[test]
+    fn missing_line_is_an_error() {
+        assert!(matches!(
+            load_elegant_file(ELEGANT_TESTFILE, Some("NOT_A_LINE")),
+            Err(LotrError::UndefinedName { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(matches!(
+            load_elegant_file("tests/not_a_file.lte", Some("DRIFT")),
+            Err(LotrError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn lines_can_be_repeated_and_reversed() {
+        let sim = load_elegant_file(ELEGANT_TESTFILE, Some("REPEATED")).unwrap();
+        let names: Vec<&str> = sim.elements.iter().map(|ele| ele.name.as_str()).collect();
+        let (drift, sbend) = ("test_drift", "test_sbend");
+        let (kquad, hkick) = ("test_kquad", "test_hkick");
+        assert_eq!(
+            names,
+            [
+                drift, sbend, drift, sbend, sbend, drift, kquad, hkick, kquad, hkick, kquad, hkick,
+                sbend, drift, sbend, drift, drift
+            ]
+        );
+    }
+
+    #[test]
+    fn csrcsbend_has_csr() {
+        let sim = load_elegant_file(ELEGANT_TESTFILE, Some("CSRCSBEND")).unwrap();
+        match &sim.elements[0].ele_type {
+            EleType::Dipole(details) => assert_eq!(
+                details.csr,
+                Some(Csr {
+                    bins: 600,
+                    smoothing: 1
+                })
+            ),
+            other => panic!("Expected a dipole, got {other:?}"),
+        }
+        let sim = load_elegant_file(ELEGANT_TESTFILE, Some("SBEND")).unwrap();
+        assert!(matches!(
+            &sim.elements[0].ele_type,
+            EleType::Dipole(DipoleDetails { csr: None, .. })
+        ));
+    }
+
+    #[test]
+    fn rfdf_is_a_deflector() {
+        let sim = load_elegant_file(ELEGANT_TESTFILE, Some("RFDF")).unwrap();
+        match &sim.elements[0].ele_type {
+            EleType::Deflector(details) => {
+                assert_eq!(details.voltage, 61.4e6);
+                assert_eq!(details.frequency, 2.9985e9);
+            }
+            other => panic!("Expected a deflector, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn wiggler_can_be_a_laser_heater() {
+        let sim = load_elegant_file(ELEGANT_TESTFILE, Some("WIGGLER")).unwrap();
+        match &sim.elements[0].ele_type {
+            EleType::Wiggler(details) => {
+                assert_eq!(details.k, 2.23);
+                assert!((details.period - 2f64 * 4.95 / 328f64).abs() < 1e-15);
+                assert!(details.laser_heater.is_none());
+            }
+            other => panic!("Expected a wiggler, got {other:?}"),
+        }
+        let mut sim = load_elegant_file(ELEGANT_TESTFILE, Some("LASER_HEATER")).unwrap();
+        sim.set_laser_heater("test_laser_heater", 800e-9, 10e3, 1f64)
+            .unwrap();
+        match &sim.elements[0].ele_type {
+            EleType::Wiggler(details) => {
+                assert_eq!(details.k, WigglerDetails::k_from_field(0.3, 0.05));
+                assert_eq!(
+                    details.laser_heater,
+                    Some(LaserHeater {
+                        wavelength: 800e-9,
+                        amplitude: 10e3,
+                        phase_spread: 1f64,
+                        seed: element_seed(DEFAULT_SEED, 0),
+                    })
+                );
+            }
+            other => panic!("Expected a wiggler, got {other:?}"),
+        }
+        assert!(matches!(
+            sim.set_laser_heater("test_laser_heater", -800e-9, 10e3, 1f64),
+            Err(LotrError::InvalidElement { .. })
+        ));
+        let mut sim = load_elegant_file(ELEGANT_TESTFILE, Some("DRIFT")).unwrap();
+        assert!(matches!(
+            sim.set_laser_heater("test_drift", 800e-9, 10e3, 1f64),
+            Err(LotrError::InvalidElement { .. })
+        ));
+    }
+
+    #[test]
+    fn lscdrift_has_lsc() {
+        let sim = load_elegant_file(ELEGANT_TESTFILE, Some("LSCDRIFT")).unwrap();
+        assert_eq!(sim.elements[0].length, 2f64);
+        match &sim.elements[0].ele_type {
+            EleType::Drift(details) => assert_eq!(
+                details.lsc,
+                Some(Lsc {
+                    radius: 1e-4,
+                    bins: 50
+                })
+            ),
+            other => panic!("Expected a drift, got {other:?}"),
+        }
+    }
 
     #[test]
     fn track_thru_drift() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "DRIFT");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("DRIFT")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(DRIFT_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(DRIFT_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(DRIFT_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(DRIFT_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(DRIFT_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_sbend() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "SBEND");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("SBEND")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(SBEND_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(SBEND_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(SBEND_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(SBEND_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(SBEND_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_marker() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "MARKER");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("MARKER")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(MARKER_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(MARKER_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(MARKER_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(MARKER_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(MARKER_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_hkick() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "HKICK");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("HKICK")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(HKICK_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(HKICK_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(HKICK_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(HKICK_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(HKICK_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_vkick() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "VKICK");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("VKICK")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(VKICK_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(VKICK_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(VKICK_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(VKICK_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(VKICK_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_kquad() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "KQUAD");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("KQUAD")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(KQUAD_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(KQUAD_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(KQUAD_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(KQUAD_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(KQUAD_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_zero_crossing_rfcw() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "RFCW_ZEROCROSSING");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation =
+            load_elegant_file(ELEGANT_TESTFILE, Some("RFCW_ZEROCROSSING")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(RFCW_ZEROCROSSING_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(RFCW_ZEROCROSSING_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(RFCW_ZEROCROSSING_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(RFCW_ZEROCROSSING_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(RFCW_ZEROCROSSING_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_crest_rfcw() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "RFCW_CREST");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("RFCW_CREST")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(RFCW_CREST_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(RFCW_CREST_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(RFCW_CREST_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(RFCW_CREST_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(RFCW_CREST_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_rfdf() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "RFDF");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("RFDF")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(RFDF_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(RFDF_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(RFDF_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(RFDF_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(RFDF_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_wiggler() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "WIGGLER");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("WIGGLER")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(WIGGLER_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(WIGGLER_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(WIGGLER_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(WIGGLER_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(WIGGLER_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_csrcsbend() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "CSRCSBEND");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("CSRCSBEND")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(CSRCSBEND_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(CSRCSBEND_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         if let Ok(mut file_true) = File::open(CSRCSBEND_BEAM_TRUE) {
-            let mut file_test = File::open(CSRCSBEND_BEAM_TEST).unwrap();
+            let mut file_test = File::open(test_output(CSRCSBEND_BEAM_TEST)).unwrap();
             assert!(diff_files(&mut file_true, &mut file_test));
         } else {
             panic!("No file to compare against");
@@ -926,141 +1532,209 @@ mod tests {
 
     #[test]
     fn track_thru_rben() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "RBEN");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("RBEN")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(RBEN_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(RBEN_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(RBEN_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(RBEN_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(RBEN_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_sben() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "SBEN");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("SBEN")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(SBEN_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(SBEN_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(SBEN_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(SBEN_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(SBEN_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_ksext() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "KSEXT");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("KSEXT")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(KSEXT_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(KSEXT_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(KSEXT_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(KSEXT_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(KSEXT_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_scraper() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "SCRAPER");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("SCRAPER")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(SCRAPER_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(SCRAPER_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(SCRAPER_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(SCRAPER_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(SCRAPER_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_ecol() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "ECOL");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("ECOL")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(ECOL_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(ECOL_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(ECOL_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(ECOL_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(ECOL_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
+    #[test]
+    fn dispersive_collimators_lose_particles() {
+        for (line, expected_transmission) in [
+            ("ECOL_DISPERSIVE", 1f64 / 3f64),
+            ("SCRAPER_DISPERSIVE", 2f64 / 3f64),
+            ("PFILTER", 1f64 / 3f64),
+        ] {
+            let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some(line)).unwrap();
+            let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
+            sim.input_beam = newsim.input_beam;
+            sim.rescale_acc_energy(newsim.input_beam_ke);
+            if line != "PFILTER" {
+                // Without the dispersion, an aperture lets everything through
+                sim.track().unwrap();
+                assert_eq!(sim.output_beam.transmission(), 1f64);
+                sim.current = 0;
+                let name = sim.elements[0].name.clone();
+                sim.set_dispersion(&name, 0.5).unwrap();
+            }
+            sim.track().unwrap();
+            let beam = &sim.output_beam;
+            assert!((beam.transmission() - expected_transmission).abs() < 1e-12);
+            for (particle, loss) in beam.pos.outer_iter().zip(&beam.lost) {
+                match loss {
+                    Some(loss) => {
+                        assert_eq!(loss.element, 0);
+                        assert_eq!(loss.name, sim.elements[0].name);
+                        assert!(particle[1] > 0f64 || line != "SCRAPER_DISPERSIVE");
+                    }
+                    None => assert!(particle[1] <= 0f64),
+                }
+            }
+        }
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("PFILTER")).unwrap();
+        assert!(matches!(
+            sim.set_dispersion("test_pfilter", 0.5),
+            Err(LotrError::InvalidElement { .. })
+        ));
+        assert!(matches!(
+            sim.set_dispersion("not_an_element", 0.5),
+            Err(LotrError::UndefinedName { .. })
+        ));
+    }
+
+    #[test]
+    fn loss_map_counts_losses_per_element() {
+        let mut sim: Simulation =
+            load_elegant_file(ELEGANT_TESTFILE, Some("SCRAPER_DISPERSIVE")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
+        sim.input_beam = newsim.input_beam;
+        sim.input_beam.charge = 33e-12;
+        sim.rescale_acc_energy(newsim.input_beam_ke);
+        sim.set_dispersion("test_scraper_dispersive", 0.5).unwrap();
+        sim.track().unwrap();
+        let mut loss_map = Vec::new();
+        out_lossmap(&mut loss_map, &sim).unwrap();
+        let loss_map = String::from_utf8(loss_map).unwrap();
+        let row: Vec<f64> = loss_map
+            .trim()
+            .split(", ")
+            .map(|val| val.parse().unwrap())
+            .collect();
+        assert_eq!(row.len(), 5);
+        assert_eq!(row[..3], [0f64, 0f64, 11f64]);
+        assert!((row[3] - 11e-12).abs() < 1e-24);
+        assert!((row[4] - 2f64 / 3f64).abs() < 1e-12);
+    }
+
     #[test]
     fn track_thru_monitor() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "MONITOR");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("MONITOR")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(MONITOR_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(MONITOR_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(MONITOR_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(MONITOR_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(MONITOR_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_moni() {
-        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, "MONI");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(ELEGANT_TESTFILE, Some("MONI")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(MONI_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(MONI_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(MONI_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(MONI_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(MONI_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn track_thru_spf() {
-        let mut sim: Simulation = load_elegant_file(SPF_TESTFILE, "SPF");
-        let newsim = load_lotr_file(BEAM_TESTFILE);
+        let mut sim: Simulation = load_elegant_file(SPF_TESTFILE, Some("SPF")).unwrap();
+        let newsim = load_lotr_file(BEAM_TESTFILE).unwrap();
         sim.input_beam = newsim.input_beam;
         sim.rescale_acc_energy(newsim.input_beam_ke);
-        sim.track();
-        if let Ok(mut file) = File::create(SPF_BEAM_TEST) {
-            print_beam(&mut file, &sim.output_beam);
+        sim.track().unwrap();
+        if let Ok(mut file) = File::create(test_output(SPF_BEAM_TEST)) {
+            print_beam(&mut file, &sim.output_beam).unwrap();
         }
 
         let mut file_true = File::open(SPF_BEAM_TRUE).unwrap();
-        let mut file_test = File::open(SPF_BEAM_TEST).unwrap();
+        let mut file_test = File::open(test_output(SPF_BEAM_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 
     #[test]
     fn energyprofile_is_correct() {
-        let sim: Simulation = load_elegant_file(SPF_TESTFILE, "SPF");
-        if let Ok(mut file) = File::create(SPF_ENERGY_PROFILE_TEST) {
-            out_energyprofile(&mut file, &sim);
+        let sim: Simulation = load_elegant_file(SPF_TESTFILE, Some("SPF")).unwrap();
+        if let Ok(mut file) = File::create(test_output(SPF_ENERGY_PROFILE_TEST)) {
+            out_energyprofile(&mut file, &sim).unwrap();
         }
 
         let mut file_true = File::open(SPF_ENERGY_PROFILE_TRUE).unwrap();
-        let mut file_test = File::open(SPF_ENERGY_PROFILE_TEST).unwrap();
+        let mut file_test = File::open(test_output(SPF_ENERGY_PROFILE_TEST)).unwrap();
         assert!(diff_files(&mut file_true, &mut file_test));
     }
 }
@@ -1,10 +1,21 @@
-use crate::beam::{gamma_2_beta, ke_2_gamma, Beam, C, MASS};
-use crate::elements::{make_acccav, make_dipole, make_drift, AccCavDetails, EleType, Element};
+use crate::beam::{
+    delta_2_gamma, gamma_2_beta, gamma_2_ke, ke_2_gamma, Beam, BeamStats, TrackingOrder, C,
+};
+use crate::distribution::{BeamDistribution, ZDistribution};
+use crate::elements::{
+    make_acccav, make_collimator, make_deflector, make_dipole, make_drift, make_wiggler,
+    AccCavDetails, CollimatorDetails, EleType, Element, LaserHeater, PhaseConvention,
+    VoltageConvention, WigglerDetails,
+};
+use crate::error::{FileLoc, LotrError};
+use crate::random::{element_seed, DEFAULT_SEED};
+use crate::wakefield::Wake;
 use core::f64::consts::PI;
-use ndarray::Array2;
+use ndarray::{stack, Array1, Array2, ArrayView2, Axis};
+use ndarray_npy::{write_npy, WriteNpyError};
 use std::fmt;
 use std::fs::read_to_string;
-use std::process::exit;
+use std::io::{self, Write};
 
 pub struct Simulation {
     pub elements: Vec<Element>,
@@ -14,95 +25,395 @@ pub struct Simulation {
     pub breakpoints: Vec<usize>,
     pub breakpoints_passed: Vec<usize>,
     pub current: usize,
+    /// z, delta and the id of each particle before the first element and after each tracked
+    /// element, in order of their ids. Only kept when there is a `save_filename` to write it to.
+    pub history: Vec<Array2<f64>>,
+    /// Statistics of the beam before the first element and after each tracked element
+    pub stats_history: Vec<BeamStats>,
+    pub save_filename: Option<String>,
+    pub tracking_order: TrackingOrder,
+    /// Seed from which each element with random kicks draws its own seed
+    pub random_seed: u64,
+    /// Moves the reference energy to the mean energy of the beam after each element, as
+    /// elegant's `always_change_p0` does, and redesigns the elements that follow for it
+    pub always_change_p0: bool,
+    /// gamma of the reference particle that the output beam is measured from, once
+    /// `always_change_p0` has moved it after the last tracked element
+    pub output_gamma: Option<f64>,
 }
 
 impl Simulation {
-    pub fn step(&mut self) {
+    /// Tracks the output beam through the current element. The history is saved if that was
+    /// the last element and there is a `save_filename`.
+    pub fn step(&mut self) -> Result<(), LotrError> {
         if self.current == self.elements.len() {
-            println!("ERROR: Have already tracked to the last element. Consider using `reset`.");
-            return;
+            return Err(LotrError::TrackedToEnd);
         }
         if self.current == 0 {
-            self.output_beam = self.input_beam.clone();
+            self.start_tracking();
         }
-        println!(
-            "Stepping {} particles through a single element...",
-            self.input_beam.pos.shape()[0]
-        );
-        self.output_beam.track(&self.elements[self.current]);
+        self.track_output_beam(self.current);
         self.current += 1;
+        self.save_history_if_finished()
     }
 
-    pub fn track(&mut self) {
+    /// Tracks the output beam from the current element to the end of the line, stopping in
+    /// front of the first breakpoint not yet passed, and returns the number of elements tracked.
+    /// The history is saved if the end was reached and there is a `save_filename`.
+    pub fn track(&mut self) -> Result<usize, LotrError> {
         if self.current == self.elements.len() {
-            println!("ERROR: Have already tracked to the last element. Consider using `reset`.");
-            return;
+            return Err(LotrError::TrackedToEnd);
         }
         if self.current == 0 {
-            self.output_beam = self.input_beam.clone();
+            self.start_tracking();
         }
-        let mut eles_to_track = self.elements.len() - self.current;
-        for bp in self.breakpoints.iter() {
-            if bp > &self.current {
-                eles_to_track = bp - self.current;
+        let start = self.current;
+        for ind in self.current..self.elements.len() {
+            if self.breakpoints.contains(&ind) && !self.breakpoints_passed.contains(&ind) {
+                self.breakpoints_passed.push(ind);
                 break;
             }
+            self.current += 1;
+            self.track_output_beam(ind);
         }
-        println!(
-            "Tracking {} particles through {} accelerator elements...",
-            self.input_beam.pos.shape()[0],
-            eles_to_track
-        );
-        for element in self.elements[self.current..].iter() {
-            if self.breakpoints.contains(&self.current)
-                && !self.breakpoints_passed.contains(&self.current)
-            {
-                println!(
-                    "Stopping at element {} ({}) due to a breakpoint",
-                    self.current, element.name
-                );
-                self.breakpoints_passed.push(self.current);
-                break;
+        self.save_history_if_finished()?;
+        Ok(self.current - start)
+    }
+
+    /// Starts the output beam again from the input beam, forgetting what has been recorded
+    fn start_tracking(&mut self) {
+        self.output_beam = self.input_beam.clone();
+        self.output_gamma = None;
+        self.history.clear();
+        self.stats_history.clear();
+        self.record_output_beam();
+    }
+
+    /// Tracks the output beam through the element at `index` and records it. With
+    /// `always_change_p0`, the elements after it are redesigned for the mean energy of the beam.
+    fn track_output_beam(&mut self, index: usize) {
+        let element = &self.elements[index];
+        self.output_beam
+            .track_with_order(element, self.tracking_order);
+        if self.always_change_p0 {
+            let gamma0 = change_p0(&mut self.output_beam, element.exit_gamma());
+            self.output_gamma = Some(gamma0);
+            self.rescale_elements(index + 1, gamma_2_ke(gamma0));
+        }
+        self.record_output_beam();
+    }
+
+    fn record_output_beam(&mut self) {
+        self.stats_history.push(self.output_beam.stats());
+        if self.save_filename.is_some() {
+            self.history.push(self.output_beam.phase_space_by_id());
+        }
+    }
+
+    /// The beam entering the element at `index`, found by tracking the input beam through the
+    /// elements before it. Every element draws the same random numbers each time it is tracked,
+    /// so this is the beam that [`Simulation::track`] passes to it.
+    pub fn beam_entering(&self, index: usize) -> Beam {
+        let mut beam = self.input_beam.clone();
+        for element in &self.elements[..index] {
+            beam.track_with_order(element, self.tracking_order);
+            if self.always_change_p0 {
+                change_p0(&mut beam, element.exit_gamma());
             }
-            self.current += 1;
-            self.output_beam.track(element);
         }
+        beam
+    }
+
+    /// Writes z, delta and the id of each particle before the first element and after every
+    /// element as a [n_elements + 1, n_particles, 3] numpy array, with the particles in order of
+    /// their ids. The coordinates of a particle are NaN once it has been lost.
+    pub fn save_history(&self, filename: &str) -> Result<(), LotrError> {
+        let write_error = |source| LotrError::Write {
+            filename: filename.to_string(),
+            source,
+        };
+        let views: Vec<ArrayView2<f64>> = self.history.iter().map(|pos| pos.view()).collect();
+        let history = stack(Axis(0), &views)
+            .map_err(|e| write_error(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        write_npy(filename, &history).map_err(|e| match e {
+            WriteNpyError::Io(source) => write_error(source),
+            other => write_error(io::Error::other(other)),
+        })
+    }
+
+    fn save_history_if_finished(&self) -> Result<(), LotrError> {
+        if self.current != self.elements.len() {
+            return Ok(());
+        }
+        match &self.save_filename {
+            Some(filename) => self.save_history(filename),
+            None => Ok(()),
+        }
+    }
+
+    /// gamma of the reference particle that the delta of the input beam is measured from
+    pub fn input_beam_gamma(&self) -> f64 {
+        self.elements
+            .first()
+            .map_or(ke_2_gamma(self.input_beam_ke), |ele| ele.gamma)
+    }
+
+    /// gamma of the reference particle that the delta of the output beam is measured from: the
+    /// one at the exit of the last tracked element
+    pub fn output_beam_gamma(&self) -> f64 {
+        if let Some(gamma0) = self.output_gamma {
+            return gamma0;
+        }
+        match self.current.checked_sub(1) {
+            Some(last) => self.elements[last].exit_gamma(),
+            None => self.input_beam_gamma(),
+        }
+    }
+
+    /// Reseeds the random kicks of every element from `seed`, as given to each element by
+    /// [`element_seed`]
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random_seed = seed;
+        for (ind, ele) in self.elements.iter_mut().enumerate() {
+            match &mut ele.ele_type {
+                EleType::Dipole(details) => {
+                    if let Some(isr_seed) = details.isr_seed.as_mut() {
+                        *isr_seed = element_seed(seed, ind);
+                    }
+                }
+                EleType::Wiggler(WigglerDetails {
+                    laser_heater: Some(laser_heater),
+                    ..
+                }) => laser_heater.seed = element_seed(seed, ind),
+                _ => {}
+            }
+        }
+    }
+
+    /// Sets the dispersion (m) at each ECOL, RCOL or SCRAPER named `name`, from which the energy
+    /// acceptance of its horizontal aperture follows
+    pub fn set_dispersion(&mut self, name: &str, dispersion: f64) -> Result<(), LotrError> {
+        let mut found = false;
+        for ele in self.elements.iter_mut().filter(|ele| ele.name == name) {
+            match &mut ele.ele_type {
+                EleType::Collimator(details) if details.aperture.is_some() => {
+                    details.set_dispersion(dispersion);
+                    found = true;
+                }
+                _ => {
+                    return Err(LotrError::InvalidElement {
+                        name: name.to_string(),
+                        message: "Only the apertures of ECOL, RCOL and SCRAPER elements have a \
+                                  dispersion"
+                            .to_string(),
+                    })
+                }
+            }
+        }
+        if !found {
+            return Err(LotrError::UndefinedName {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Makes each wiggler named `name` a laser heater, modulating the energy of each particle by
+    /// `amplitude` (eV) at the laser `wavelength` (m) with a random phase slippage spread over
+    /// `phase_spread` radians. See [`LaserHeater`].
+    pub fn set_laser_heater(
+        &mut self,
+        name: &str,
+        wavelength: f64,
+        amplitude: f64,
+        phase_spread: f64,
+    ) -> Result<(), LotrError> {
+        if wavelength <= 0f64 {
+            return Err(LotrError::InvalidElement {
+                name: name.to_string(),
+                message: "A laser heater needs a positive wavelength".to_string(),
+            });
+        }
+        let mut found = false;
+        for (ind, ele) in self.elements.iter_mut().enumerate() {
+            if ele.name != name {
+                continue;
+            }
+            match &mut ele.ele_type {
+                EleType::Wiggler(details) => {
+                    details.laser_heater = Some(LaserHeater {
+                        wavelength,
+                        amplitude,
+                        phase_spread,
+                        seed: element_seed(self.random_seed, ind),
+                    });
+                    found = true;
+                }
+                _ => {
+                    return Err(LotrError::InvalidElement {
+                        name: name.to_string(),
+                        message: "Only a wiggler can be a laser heater".to_string(),
+                    })
+                }
+            }
+        }
+        if !found {
+            return Err(LotrError::UndefinedName {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Tracks `beam` from now on, with its delta measured from the kinetic energy `ke` (eV), and
+    /// rescales the accelerator to that energy
+    pub fn set_input_beam(&mut self, beam: Beam, ke: f64) {
+        self.input_beam = beam;
+        self.input_beam_ke = ke;
+        self.rescale_acc_energy(ke);
     }
 
     pub fn find_element_by_name(&self, searchterm: String) -> Option<usize> {
         self.elements.iter().position(|x| x.name == searchterm)
     }
 
-    pub fn rescale_acc_energy(&mut self, mut new_ke: f64) {
-        for ele in self.elements.iter_mut() {
-            match ele.ele_type {
-                EleType::Drift => *ele = make_drift(ele.name.clone(), ele.length, new_ke / MASS),
-                EleType::Dipole => {
+    pub fn rescale_acc_energy(&mut self, new_ke: f64) {
+        self.rescale_elements(0, new_ke);
+    }
+
+    /// Redesigns the elements from `start` onwards for a beam entering them with kinetic energy
+    /// `new_ke` (eV)
+    fn rescale_elements(&mut self, start: usize, mut new_ke: f64) {
+        for ele in self.elements[start..].iter_mut() {
+            match &ele.ele_type {
+                EleType::Drift(details) => {
+                    let details = details.clone();
+                    *ele = make_drift(ele.name.clone(), ele.length, ke_2_gamma(new_ke));
+                    ele.ele_type = EleType::Drift(details);
+                }
+                EleType::Dipole(details) => {
+                    let details = details.clone();
                     *ele = make_dipole(
                         ele.name.clone(),
                         ele.length,
                         ele.params["angle"],
-                        new_ke / MASS,
+                        ke_2_gamma(new_ke),
                     )
+                    .expect("dipole lengths are checked when the element is first made");
+                    ele.ele_type = EleType::Dipole(details);
+                    new_ke -= ele.design_energy_loss();
+                }
+                EleType::Wiggler(details) => {
+                    *ele = make_wiggler(
+                        ele.name.clone(),
+                        ele.length,
+                        ke_2_gamma(new_ke),
+                        details.clone(),
+                    );
+                }
+                EleType::Collimator(details) => {
+                    *ele = make_collimator(
+                        ele.name.clone(),
+                        ele.length,
+                        ke_2_gamma(new_ke),
+                        details.clone(),
+                    );
+                }
+                EleType::Deflector(details) => {
+                    *ele = make_deflector(ele.name.clone(), details.clone(), ke_2_gamma(new_ke));
                 }
                 EleType::AccCav(details) => {
-                    new_ke += details.voltage * details.phase.cos();
-                    *ele = make_acccav(ele.name.clone(), details, ke_2_gamma(new_ke));
+                    let energy_gain = details.design_energy_gain();
+                    *ele = make_acccav(ele.name.clone(), details.clone(), ke_2_gamma(new_ke));
+                    new_ke += energy_gain;
                 }
             }
         }
     }
 }
 
-pub fn load_lotr_file(filename: &str) -> Simulation {
-    let tokens = tokenize_file_contents(filename);
-    parse_tokens(&tokens)
+/// Measures the delta of `beam`, leaving an element whose reference has `gamma0`, from the mean
+/// energy of its particles instead, and returns the gamma of that new reference. A beam that has
+/// lost every particle keeps its reference.
+fn change_p0(beam: &mut Beam, gamma0: f64) -> f64 {
+    let mean_gamma = delta_2_gamma(beam.stats().mean_delta, gamma0);
+    if !mean_gamma.is_finite() {
+        return gamma0;
+    }
+    beam.change_reference(gamma0, mean_gamma);
+    mean_gamma
+}
+
+pub fn out_energyprofile(sink: &mut impl Write, sim: &Simulation) -> io::Result<()> {
+    let mut z = 0f64;
+    for (ind, ele) in sim.elements.iter().enumerate() {
+        writeln!(sink, "{}, {}, {}", ind, z, ele.gamma)?;
+        z += ele.length;
+    }
+    Ok(())
+}
+
+/// Writes the beam statistics before the first element and after each tracked element.
+/// Each row is: element count, s at the exit of that element, then the statistics. Nothing is
+/// written before the beam has been tracked.
+pub fn out_statsprofile(sink: &mut impl Write, sim: &Simulation) -> io::Result<()> {
+    let mut z = 0f64;
+    for (ind, stats) in sim.stats_history.iter().enumerate() {
+        if ind > 0 {
+            z += sim.elements[ind - 1].length;
+        }
+        writeln!(
+            sink,
+            "{}, {}, {}, {}, {}, {}, {}, {}",
+            ind,
+            z,
+            stats.mean_z,
+            stats.rms_z,
+            stats.mean_delta,
+            stats.rms_delta,
+            stats.chirp,
+            stats.emittance
+        )?;
+    }
+    Ok(())
 }
 
-struct FileLoc {
-    filename: String,
-    row: usize,
-    col: usize,
+/// Writes a row for each tracked element: its index, s at its exit, the number of particles and
+/// the charge (C) lost in it, and the fraction of the charge left in the beam after it. Nothing
+/// is written before the beam has been tracked.
+pub fn out_lossmap(sink: &mut impl Write, sim: &Simulation) -> io::Result<()> {
+    let beam = &sim.output_beam;
+    let num_tracked = sim.stats_history.len().saturating_sub(1);
+    let charges = beam.particle_charges();
+    let mut num_lost = vec![0usize; num_tracked];
+    let mut charge_lost = vec![0f64; num_tracked];
+    for (loss, charge) in beam.lost.iter().zip(&charges) {
+        if let Some(loss) = loss.as_ref().filter(|loss| loss.element < num_tracked) {
+            num_lost[loss.element] += 1;
+            charge_lost[loss.element] += charge;
+        }
+    }
+    let total_weight = beam.weights.sum();
+    let mut z = 0f64;
+    for (ind, ele) in sim.elements[..num_tracked].iter().enumerate() {
+        z += ele.length;
+        let transmission = if total_weight == 0f64 {
+            0f64
+        } else {
+            beam.weights_after(ind + 1).sum() / total_weight
+        };
+        writeln!(
+            sink,
+            "{}, {}, {}, {}, {}",
+            ind, z, num_lost[ind], charge_lost[ind], transmission
+        )?;
+    }
+    Ok(())
+}
+
+pub fn load_lotr_file(filename: &str) -> Result<Simulation, LotrError> {
+    let tokens = tokenize_file_contents(filename)?;
+    parse_tokens(&tokens)
 }
 
 #[derive(Debug, PartialEq)]
@@ -142,7 +453,7 @@ fn parse_word(input: &mut String, loc: FileLoc) -> Token {
     }
 }
 
-fn parse_digit(input: &mut String, loc: FileLoc) -> Token {
+fn parse_digit(input: &mut String, loc: FileLoc) -> Result<Token, LotrError> {
     let mut already_decimal: bool = false;
     let mut already_exp: bool = false;
     let mut value: String = chop_character(input).to_string();
@@ -151,20 +462,29 @@ fn parse_digit(input: &mut String, loc: FileLoc) -> Token {
             value.push(chop_character(input));
         } else if input.starts_with("e-") {
             if already_exp {
-                panic!("Attempt to add 'e' to a digit twice");
+                return Err(LotrError::syntax(
+                    &loc,
+                    "Attempt to add 'e' to a digit twice",
+                ));
             }
             already_exp = true;
             value.push(chop_character(input));
             value.push(chop_character(input));
         } else if input.starts_with('e') {
             if already_exp {
-                panic!("Attempt to add 'e' to a digit twice");
+                return Err(LotrError::syntax(
+                    &loc,
+                    "Attempt to add 'e' to a digit twice",
+                ));
             }
             already_exp = true;
             value.push(chop_character(input));
         } else if input.starts_with('.') {
             if already_decimal {
-                panic!("Attempt to add a second decimal point to a digit");
+                return Err(LotrError::syntax(
+                    &loc,
+                    "Attempt to add a second decimal point to a digit",
+                ));
             }
             already_decimal = true;
             value.push(chop_character(input));
@@ -172,24 +492,25 @@ fn parse_digit(input: &mut String, loc: FileLoc) -> Token {
             break;
         }
     }
-    Token {
+    Ok(Token {
         token_type: TokenType::Value,
         value,
         loc,
-    }
+    })
 }
 
 fn chop_character(input: &mut String) -> char {
     input.remove(0)
 }
 
-fn tokenize_file_contents(filename: &str) -> Vec<Token> {
+fn tokenize_file_contents(filename: &str) -> Result<Vec<Token>, LotrError> {
     let mut contents = match read_to_string(filename) {
         Ok(contents) => contents,
-        Err(e) => {
-            eprintln!("{}", e);
-            eprintln!("Could not open file: '{}'", filename);
-            exit(1);
+        Err(source) => {
+            return Err(LotrError::Io {
+                filename: filename.to_string(),
+                source,
+            })
         }
     };
     let mut tokens: Vec<Token> = vec![];
@@ -226,7 +547,7 @@ fn tokenize_file_contents(filename: &str) -> Vec<Token> {
                     col,
                     filename: filename.to_string(),
                 },
-            );
+            )?;
             col += tok.value.len();
             tokens.push(tok);
         } else if contents.starts_with('{') {
@@ -266,21 +587,26 @@ fn tokenize_file_contents(filename: &str) -> Vec<Token> {
             });
             col += 1;
         } else if contents.starts_with("//") {
-            let mut next = chop_character(&mut contents);
-            while next != '\n' {
-                next = chop_character(&mut contents);
-            }
+            while !contents.is_empty() && chop_character(&mut contents) != '\n' {}
             row += 1;
             col = 1;
         } else {
+            let loc = FileLoc {
+                filename: filename.to_string(),
+                row,
+                col,
+            };
             let chr = chop_character(&mut contents);
-            eprintln!("Unknown character: {}", chr);
+            return Err(LotrError::syntax(
+                &loc,
+                format!("Unknown character '{chr}'"),
+            ));
         }
     }
-    tokens
+    Ok(tokens)
 }
 
-fn parse_tokens(token_list: &[Token]) -> Simulation {
+fn parse_tokens(token_list: &[Token]) -> Result<Simulation, LotrError> {
     use TokenType::*;
     let mut acc = Simulation {
         elements: vec![],
@@ -290,8 +616,17 @@ fn parse_tokens(token_list: &[Token]) -> Simulation {
         breakpoints: Vec::new(),
         breakpoints_passed: Vec::new(),
         current: 0,
+        history: Vec::new(),
+        stats_history: Vec::new(),
+        save_filename: None,
+        tracking_order: Default::default(),
+        random_seed: DEFAULT_SEED,
+        always_change_p0: false,
+        output_gamma: None,
     };
     let mut beam_vec: Vec<[f64; 2]> = vec![];
+    let mut beam_charge = 0f64;
+    let mut beam_weights: Vec<f64> = vec![];
     let mut ind: usize = 0;
     let mut sync_ke: f64;
     let mut design_ke: f64;
@@ -300,77 +635,155 @@ fn parse_tokens(token_list: &[Token]) -> Simulation {
         let tok = &token_list[ind];
         if tok.token_type == Word && tok.value == "beam" {
             ind += 1;
-            token_check(&token_list[ind], Ocurly);
+            token_check(token_list, ind, Ocurly)?;
             ind += 1;
-            token_check(&token_list[ind], Word);
-            match token_list[ind].value.as_str() {
+            token_check(token_list, ind, Word)?;
+            match token_at(token_list, ind)?.value.as_str() {
                 "design_ke" => {
                     ind += 1;
-                    token_check(&token_list[ind], Colon);
+                    token_check(token_list, ind, Colon)?;
                     ind += 1;
-                    token_check(&token_list[ind], Value);
-                    design_ke = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                    design_ke = value_at(token_list, ind)?;
                     acc.input_beam_ke = design_ke;
                     design_beta = gamma_2_beta(ke_2_gamma(design_ke));
                     ind += 1;
                 }
-                _ => {
-                    eprintln!("Expected 'design_ke', but got {}", token_list[ind].value);
-                    exit(1);
+                other => {
+                    return Err(LotrError::syntax(
+                        &token_list[ind].loc,
+                        format!("Expected 'design_ke', but got '{other}'"),
+                    ))
                 }
             }
-            while token_list[ind].token_type != Ccurly {
-                match token_list[ind].value.as_str() {
+            while token_at(token_list, ind)?.token_type != Ccurly {
+                match token_at(token_list, ind)?.value.as_str() {
                     "particles" => {
                         ind += 1;
-                        token_check(&token_list[ind], Ocurly);
+                        token_check(token_list, ind, Ocurly)?;
                         ind += 1;
-                        while token_list[ind].token_type != Ccurly {
-                            token_check(&token_list[ind], Value);
-                            token_check(&token_list[ind + 1], Value);
-                            let z = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        while token_at(token_list, ind)?.token_type != Ccurly {
+                            let z = value_at(token_list, ind)?;
                             ind += 1;
-                            let del_e = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                            let del_e = value_at(token_list, ind)?;
                             ind += 1;
                             beam_vec.push([z, (1f64 / design_beta) * (del_e / design_ke)]);
                         }
                     }
-                    _ => todo!("Implement more beam definitions"),
+                    "distribution" => {
+                        ind += 1;
+                        token_check(token_list, ind, Ocurly)?;
+                        ind += 1;
+                        let mut dist: BeamDistribution = Default::default();
+                        while token_at(token_list, ind)?.token_type != Ccurly {
+                            let key = token_check(token_list, ind, Word)?.value.as_str();
+                            ind += 1;
+                            token_check(token_list, ind, Colon)?;
+                            ind += 1;
+                            if key == "z_dist" {
+                                token_check(token_list, ind, Word)?;
+                                dist.z_dist = match token_at(token_list, ind)?.value.as_str() {
+                                    "gaussian" => ZDistribution::Gaussian,
+                                    "uniform" => ZDistribution::Uniform,
+                                    "flattop" => ZDistribution::FlatTop,
+                                    other => {
+                                        return Err(LotrError::syntax(
+                                            &token_list[ind].loc,
+                                            format!("Unknown z_dist '{other}'. Expected 'gaussian', 'uniform' or 'flattop'"),
+                                        ))
+                                    }
+                                };
+                                ind += 1;
+                                continue;
+                            }
+                            let val = value_at(token_list, ind)?;
+                            match key {
+                                "num_particles" => dist.num_particles = val as usize,
+                                "seed" => dist.seed = val as u64,
+                                "sigma_z" => dist.sigma_z = val,
+                                "length_z" => dist.length_z = val,
+                                "rise_z" => dist.rise_z = val,
+                                "sigma_e" => dist.sigma_e = val,
+                                "chirp" => dist.chirp = val,
+                                "curvature" => dist.curvature = val,
+                                _ => {
+                                    return Err(LotrError::syntax(
+                                        &token_list[ind - 2].loc,
+                                        format!("Unknown distribution parameter '{key}'"),
+                                    ))
+                                }
+                            }
+                            ind += 1;
+                        }
+                        beam_vec.extend(dist.generate(design_ke));
+                    }
+                    "charge" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        beam_charge = value_at(token_list, ind)?;
+                    }
+                    "weights" => {
+                        ind += 1;
+                        token_check(token_list, ind, Ocurly)?;
+                        ind += 1;
+                        while token_at(token_list, ind)?.token_type != Ccurly {
+                            let weight = value_at(token_list, ind)?;
+                            beam_weights.push(weight);
+                            ind += 1;
+                        }
+                    }
+                    other => {
+                        return Err(LotrError::syntax(
+                            &token_list[ind].loc,
+                            format!("Unknown beam definition '{other}'"),
+                        ))
+                    }
                 }
                 ind += 1;
             }
             acc.input_beam = Beam::new(Array2::from(beam_vec.clone()));
+            acc.input_beam.charge = beam_charge;
+            if !beam_weights.is_empty() {
+                if beam_weights.len() != beam_vec.len() {
+                    return Err(LotrError::syntax(
+                        &tok.loc,
+                        format!(
+                            "Got {} weights for {} particles",
+                            beam_weights.len(),
+                            beam_vec.len()
+                        ),
+                    ));
+                }
+                acc.input_beam.weights = Array1::from(beam_weights.clone());
+            }
         }
         if tok.token_type == Word && tok.value == "accelerator" {
             ind += 1;
-            token_check(&token_list[ind], Ocurly);
+            token_check(token_list, ind, Ocurly)?;
             ind += 1;
-            token_check(&token_list[ind], Word);
-            if token_list[ind].value != "initial_ke" {
-                eprintln!(
-                    "{}:{}:{}: The first item in 'accelerator' should be 'initial_ke', not {}'",
-                    token_list[ind].loc.filename,
-                    token_list[ind].loc.row,
-                    token_list[ind].loc.col,
-                    token_list[ind].value,
-                );
-                exit(1);
+            let first = token_check(token_list, ind, Word)?;
+            if first.value != "initial_ke" {
+                return Err(LotrError::syntax(
+                    &first.loc,
+                    format!(
+                        "The first item in 'accelerator' should be 'initial_ke', not '{}'",
+                        first.value
+                    ),
+                ));
             }
             ind += 1;
-            token_check(&token_list[ind], Colon);
+            token_check(token_list, ind, Colon)?;
             ind += 1;
-            token_check(&token_list[ind], Value);
-            sync_ke = token_list[ind].value.parse::<f64>().expect("uh oh!");
+            sync_ke = value_at(token_list, ind)?;
             ind += 1;
-            while token_list[ind].token_type != Ccurly {
-                let ele_type = token_list[ind].value.as_str();
+            while token_at(token_list, ind)?.token_type != Ccurly {
+                let ele_type = token_at(token_list, ind)?.value.as_str();
                 match ele_type {
                     "drift" => {
                         ind += 1;
-                        token_check(&token_list[ind], Colon);
+                        token_check(token_list, ind, Colon)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let drift_len = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let drift_len = value_at(token_list, ind)?;
                         acc.elements.push(make_drift(
                             "drift_name".to_string(),
                             drift_len,
@@ -379,10 +792,9 @@ fn parse_tokens(token_list: &[Token]) -> Simulation {
                     }
                     "corrector" => {
                         ind += 1;
-                        token_check(&token_list[ind], Colon);
+                        token_check(token_list, ind, Colon)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let drift_len = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let drift_len = value_at(token_list, ind)?;
                         acc.elements.push(make_drift(
                             "corr_name".to_string(),
                             drift_len,
@@ -391,10 +803,9 @@ fn parse_tokens(token_list: &[Token]) -> Simulation {
                     }
                     "quad" => {
                         ind += 1;
-                        token_check(&token_list[ind], Colon);
+                        token_check(token_list, ind, Colon)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let drift_len = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let drift_len = value_at(token_list, ind)?;
                         acc.elements.push(make_drift(
                             "quad_name".to_string(),
                             drift_len,
@@ -403,48 +814,111 @@ fn parse_tokens(token_list: &[Token]) -> Simulation {
                     }
                     "sext" => {
                         ind += 1;
-                        token_check(&token_list[ind], Colon);
+                        token_check(token_list, ind, Colon)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let drift_len = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let drift_len = value_at(token_list, ind)?;
                         acc.elements.push(make_drift(
                             "sext_name".to_string(),
                             drift_len,
                             ke_2_gamma(sync_ke),
                         ));
                     }
+                    "wiggler" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        let length = value_at(token_list, ind)?;
+                        ind += 1;
+                        let k = value_at(token_list, ind)?;
+                        ind += 1;
+                        let period = value_at(token_list, ind)?;
+                        acc.elements.push(make_wiggler(
+                            "wiggler_name".to_string(),
+                            length,
+                            ke_2_gamma(sync_ke),
+                            WigglerDetails {
+                                k,
+                                period,
+                                laser_heater: None,
+                            },
+                        ));
+                    }
+                    "laser_heater" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        let wavelength = value_at(token_list, ind)?;
+                        ind += 1;
+                        let amplitude = value_at(token_list, ind)?;
+                        ind += 1;
+                        let phase_spread = value_at(token_list, ind)?;
+                        let seed =
+                            element_seed(acc.random_seed, acc.elements.len().saturating_sub(1));
+                        match acc.elements.last_mut() {
+                            Some(Element {
+                                ele_type: EleType::Wiggler(details),
+                                ..
+                            }) => {
+                                details.laser_heater = Some(LaserHeater {
+                                    wavelength,
+                                    amplitude,
+                                    phase_spread,
+                                    seed,
+                                })
+                            }
+                            _ => {
+                                return Err(LotrError::syntax(
+                                    &token_list[ind].loc,
+                                    "'laser_heater' must follow a 'wiggler'",
+                                ))
+                            }
+                        }
+                    }
+                    "collimator" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        let length = value_at(token_list, ind)?;
+                        ind += 1;
+                        let delta_min = value_at(token_list, ind)?;
+                        ind += 1;
+                        let delta_max = value_at(token_list, ind)?;
+                        acc.elements.push(make_collimator(
+                            "collimator_name".to_string(),
+                            length,
+                            ke_2_gamma(sync_ke),
+                            CollimatorDetails {
+                                delta_min,
+                                delta_max,
+                                aperture: None,
+                            },
+                        ));
+                    }
                     "dipole" => {
                         ind += 1;
-                        token_check(&token_list[ind], Colon);
+                        token_check(token_list, ind, Colon)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let b_field = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let b_field = value_at(token_list, ind)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let angle = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let angle = value_at(token_list, ind)?;
                         acc.elements.push(make_dipole(
                             "dipole_name".to_string(),
                             b_field,
                             angle,
                             ke_2_gamma(sync_ke),
-                        ));
+                        )?);
                     }
                     "acccav" => {
                         ind += 1;
-                        token_check(&token_list[ind], Colon);
+                        token_check(token_list, ind, Colon)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let length = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let length = value_at(token_list, ind)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let voltage = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let voltage = value_at(token_list, ind)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let freq = token_list[ind].value.parse::<f64>().expect("uh oh!");
+                        let freq = value_at(token_list, ind)?;
                         ind += 1;
-                        token_check(&token_list[ind], Value);
-                        let phi = token_list[ind].value.parse::<f64>().expect("uh oh!");
-                        println!("Calling make_acccav in parse_tokens");
+                        let phi = value_at(token_list, ind)?;
                         // let mut params = HashMap::<String, f64>::new();
                         let k = 2f64 * PI * freq / C;
                         let details = AccCavDetails {
@@ -452,35 +926,431 @@ fn parse_tokens(token_list: &[Token]) -> Simulation {
                             voltage,
                             frequency: freq,
                             phase: phi,
+                            phase_convention: PhaseConvention::Crest,
+                            voltage_convention: VoltageConvention::Gradient,
                             wavenumber: k,
+                            wake: None,
                         };
                         // params.insert("l".to_string(), length);
                         // params.insert("v".to_string(), voltage);
                         // params.insert("freq".to_string(), freq);
                         // params.insert("phi".to_string(), phi);
+                        let energy_gain = details.design_energy_gain();
                         acc.elements.push(make_acccav(
                             "acccav_name".to_string(),
                             details,
                             ke_2_gamma(sync_ke),
                         ));
-                        sync_ke += voltage * length * phi.cos();
+                        sync_ke += energy_gain;
+                        check_design_ke(sync_ke, acc.elements.last().unwrap())?;
+                    }
+                    "phase_convention" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        let convention = match token_check(token_list, ind, Word)?.value.as_str() {
+                            "crest" => PhaseConvention::Crest,
+                            "elegant" => PhaseConvention::Elegant,
+                            other => {
+                                return Err(LotrError::syntax(
+                                    &token_list[ind].loc,
+                                    format!("Unknown phase_convention '{other}'. Expected 'crest' or 'elegant'"),
+                                ))
+                            }
+                        };
+                        match acc.elements.last_mut() {
+                            Some(Element {
+                                ele_type: EleType::AccCav(details),
+                                ..
+                            }) => {
+                                sync_ke -= details.design_energy_gain();
+                                details.phase_convention = convention;
+                                sync_ke += details.design_energy_gain();
+                            }
+                            _ => {
+                                return Err(LotrError::syntax(
+                                    &token_list[ind].loc,
+                                    "'phase_convention' must follow an 'acccav'",
+                                ))
+                            }
+                        }
+                        check_design_ke(sync_ke, acc.elements.last().unwrap())?;
+                    }
+                    "voltage_convention" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        let convention = match token_check(token_list, ind, Word)?.value.as_str() {
+                            "gradient" => VoltageConvention::Gradient,
+                            "total" => VoltageConvention::Total,
+                            other => {
+                                return Err(LotrError::syntax(
+                                    &token_list[ind].loc,
+                                    format!("Unknown voltage_convention '{other}'. Expected 'gradient' or 'total'"),
+                                ))
+                            }
+                        };
+                        match acc.elements.last_mut() {
+                            Some(Element {
+                                ele_type: EleType::AccCav(details),
+                                ..
+                            }) => {
+                                sync_ke -= details.design_energy_gain();
+                                details.voltage_convention = convention;
+                                sync_ke += details.design_energy_gain();
+                            }
+                            _ => {
+                                return Err(LotrError::syntax(
+                                    &token_list[ind].loc,
+                                    "'voltage_convention' must follow an 'acccav'",
+                                ))
+                            }
+                        }
+                        check_design_ke(sync_ke, acc.elements.last().unwrap())?;
+                    }
+                    "wake" => {
+                        ind += 1;
+                        token_check(token_list, ind, Colon)?;
+                        ind += 1;
+                        let iris_radius = value_at(token_list, ind)?;
+                        ind += 1;
+                        let gap = value_at(token_list, ind)?;
+                        ind += 1;
+                        let period = value_at(token_list, ind)?;
+                        match acc.elements.last_mut() {
+                            Some(Element {
+                                ele_type: EleType::AccCav(details),
+                                ..
+                            }) => {
+                                details.wake = Some(Wake::SlacBane {
+                                    iris_radius,
+                                    gap,
+                                    period,
+                                })
+                            }
+                            _ => {
+                                return Err(LotrError::syntax(
+                                    &token_list[ind].loc,
+                                    "'wake' must follow an 'acccav'",
+                                ))
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(LotrError::syntax(
+                            &token_list[ind].loc,
+                            format!("Element '{ele_type}' not defined."),
+                        ))
                     }
-                    _ => todo!("Element '{ele_type}' not defined."),
                 }
                 ind += 1;
             }
         }
         ind += 1;
     }
-    acc
+    Ok(acc)
+}
+
+/// A cavity may decelerate the reference particle, but not to a standstill
+fn check_design_ke(design_ke: f64, ele: &Element) -> Result<(), LotrError> {
+    if design_ke <= 0f64 {
+        return Err(LotrError::InvalidElement {
+            name: ele.name.clone(),
+            message: "The reference particle has no kinetic energy left after this cavity"
+                .to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn token_at(token_list: &[Token], ind: usize) -> Result<&Token, LotrError> {
+    token_list.get(ind).ok_or_else(|| {
+        let loc = match token_list.last() {
+            Some(tok) => tok.loc.clone(),
+            None => FileLoc {
+                filename: String::new(),
+                row: 0,
+                col: 0,
+            },
+        };
+        LotrError::syntax(&loc, "Unexpected end of file")
+    })
 }
 
-fn token_check(tok: &Token, expected: TokenType) {
+fn token_check(token_list: &[Token], ind: usize, expected: TokenType) -> Result<&Token, LotrError> {
+    let tok = token_at(token_list, ind)?;
     if tok.token_type != expected {
-        eprintln!(
-            "{}:{}:{} Expected '{}', got '{}'",
-            tok.loc.filename, tok.loc.row, tok.loc.col, expected, tok.value,
+        return Err(LotrError::syntax(
+            &tok.loc,
+            format!("Expected '{}', got '{}'", expected, tok.value),
+        ));
+    }
+    Ok(tok)
+}
+
+fn value_at(token_list: &[Token], ind: usize) -> Result<f64, LotrError> {
+    let tok = token_check(token_list, ind, TokenType::Value)?;
+    tok.value
+        .parse::<f64>()
+        .map_err(|e| LotrError::syntax(&tok.loc, format!("Could not parse '{}': {e}", tok.value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_beam::load_csv_beam;
+    use ndarray::{s, Array3};
+    use ndarray_npy::read_npy;
+
+    const ACC_TESTFILE: &str = "tests/acc_defn.lotr";
+    const CHARGED_BEAM_TESTFILE: &str = "tests/charged_beam.lotr";
+    const GENERATED_BEAM_TESTFILE: &str = "tests/generated_beam.lotr";
+    const LINEARIZER_TESTFILE: &str = "tests/linearizer.lotr";
+
+    #[test]
+    fn beam_charge_is_shared_by_weight() {
+        let sim = load_lotr_file(CHARGED_BEAM_TESTFILE).unwrap();
+        assert_eq!(sim.input_beam.charge, 100e-12);
+        assert_eq!(
+            sim.input_beam.particle_charges(),
+            Array1::from(vec![25e-12, 50e-12, 25e-12])
+        );
+    }
+
+    #[test]
+    fn beam_can_be_generated() {
+        let sim = load_lotr_file(GENERATED_BEAM_TESTFILE).unwrap();
+        let other_sim = load_lotr_file(GENERATED_BEAM_TESTFILE).unwrap();
+        assert_eq!(sim.input_beam.pos.shape(), &[10000, 2]);
+        assert_eq!(sim.input_beam.pos, other_sim.input_beam.pos);
+        assert!(sim.input_beam.pos.column(0).iter().all(|z| z.abs() < 2e-3));
+    }
+
+    #[test]
+    fn linearizer_cancels_curvature_before_chicane() {
+        let mut sim = load_lotr_file(LINEARIZER_TESTFILE).unwrap();
+        let energy_gains: Vec<f64> = sim.elements[..2]
+            .iter()
+            .map(|ele| match &ele.ele_type {
+                EleType::AccCav(details) => details.design_energy_gain(),
+                other => panic!("Expected a cavity, got {other:?}"),
+            })
+            .collect();
+        assert!(energy_gains[1] < 0f64);
+        assert_eq!(
+            sim.elements[2].gamma,
+            ke_2_gamma(1e8 + energy_gains[0] + energy_gains[1])
+        );
+
+        sim.track().unwrap();
+        let curvature = |beam: &Beam| {
+            let pos = beam.pos_by_id();
+            pos[[0, 1]] + pos[[2, 1]] - 2f64 * pos[[1, 1]]
+        };
+        let after_main_cavity = curvature(&sim.beam_entering(1));
+        let after_linearizer = curvature(&sim.beam_entering(2));
+        assert!(after_main_cavity.abs() > 1e-5);
+        assert!(after_linearizer.abs() < 1e-2 * after_main_cavity.abs());
+
+        // Without the linearizer, the curvature is carried through the chicane into the bunch
+        let z_curvature = |beam: &Beam| {
+            let pos = beam.pos_by_id();
+            pos[[0, 0]] + pos[[2, 0]] - 2f64 * pos[[1, 0]]
+        };
+        let mut unlinearized = load_lotr_file(LINEARIZER_TESTFILE).unwrap();
+        unlinearized.elements.remove(1);
+        unlinearized.rescale_acc_energy(1e8);
+        unlinearized.track().unwrap();
+        let without_linearizer = z_curvature(&unlinearized.output_beam);
+        assert!(without_linearizer.abs() > 1e-7);
+        assert!(z_curvature(&sim.output_beam).abs() < 0.1 * without_linearizer.abs());
+    }
+
+    #[test]
+    fn cavity_design_gain_is_its_gradient_times_length() {
+        let sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        // acccav: 6.0 20e6 3e9 -0.085, with a drift before and after it
+        assert_eq!(sim.elements[1].gamma, ke_2_gamma(2.5e8));
+        assert_eq!(
+            sim.elements[2].gamma,
+            ke_2_gamma(2.5e8 + 6.0 * 20e6 * (-0.085f64).cos())
+        );
+    }
+
+    #[test]
+    fn total_voltage_convention_gains_the_voltage() {
+        let sim = load_lotr_file(LINEARIZER_TESTFILE).unwrap();
+        // acccav: 0.5 9.7509e6 3e9 270, in elegant's phase convention
+        let EleType::AccCav(details) = &sim.elements[1].ele_type else {
+            panic!("Expected a cavity");
+        };
+        assert_eq!(details.voltage_convention, VoltageConvention::Total);
+        assert_eq!(details.total_voltage(), 9.7509e6);
+        assert_eq!(
+            details.design_energy_gain(),
+            9.7509e6 * (270f64.to_radians() - PI / 2f64).cos()
+        );
+    }
+
+    #[test]
+    fn rescaled_cavity_keeps_its_entry_energy() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        sim.rescale_acc_energy(3e8);
+        let cavity = sim
+            .elements
+            .iter()
+            .position(|ele| matches!(ele.ele_type, EleType::AccCav(_)))
+            .unwrap();
+        assert_eq!(sim.elements[cavity].gamma, ke_2_gamma(3e8));
+    }
+
+    #[test]
+    fn rescaled_elements_follow_the_design_energy() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        if let EleType::Dipole(details) = &mut sim.elements[3].ele_type {
+            details.synch_rad = true;
+        }
+        sim.rescale_acc_energy(3e8);
+        assert_eq!(sim.elements[0].gamma, ke_2_gamma(3e8));
+        for pair in sim.elements.windows(2) {
+            assert!((pair[1].gamma - pair[0].exit_gamma()).abs() < 1e-9 * pair[0].gamma);
+        }
+        assert!(sim.elements[3].design_energy_loss() > 0f64);
+        assert!(sim.elements[4].gamma < sim.elements[3].gamma);
+    }
+
+    #[test]
+    fn csv_beam_sets_the_design_energy() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        let (beam, ke) =
+            load_csv_beam("tests/elegant_test_runs/input/test_beam.csv", None).unwrap();
+        sim.set_input_beam(beam, ke);
+        assert_eq!(sim.input_beam_ke, ke);
+        assert_eq!(sim.input_beam_gamma(), ke_2_gamma(ke));
+        let mean_z = sim.input_beam.pos.column(0).mean().unwrap();
+        assert!(mean_z.abs() < 1e-12);
+
+        sim.track().unwrap();
+        assert_eq!(sim.output_beam.transmission(), 1f64);
+        // The chicane overcompresses the beam, so it is still centred only on entering it
+        assert!(sim.beam_entering(3).stats().mean_z.abs() < 1e-3);
+        assert!(sim.output_beam.stats().mean_delta.abs() < 0.05);
+    }
+
+    #[test]
+    fn always_change_p0_follows_the_mean_energy() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        let design_gamma = sim.elements[2].gamma;
+        sim.always_change_p0 = true;
+        sim.track().unwrap();
+        // The cavity curvature lowers the mean energy of the beam below the design energy
+        assert!(sim.elements[2].gamma < design_gamma);
+        for stats in &sim.stats_history {
+            assert!(stats.mean_delta.abs() < 1e-12);
+        }
+        let output = sim.beam_entering(sim.elements.len());
+        assert_eq!(output.pos, sim.output_beam.pos);
+    }
+
+    #[test]
+    fn random_seed_reseeds_each_element() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        let dipole = sim
+            .elements
+            .iter()
+            .position(|ele| matches!(ele.ele_type, EleType::Dipole(_)))
+            .unwrap();
+        if let EleType::Dipole(details) = &mut sim.elements[dipole].ele_type {
+            details.isr_seed = Some(0);
+        }
+        sim.set_random_seed(5);
+        assert_eq!(sim.random_seed, 5);
+        match &sim.elements[dipole].ele_type {
+            EleType::Dipole(details) => {
+                assert_eq!(details.isr_seed, Some(element_seed(5, dipole)))
+            }
+            other => panic!("Expected a dipole, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn history_is_saved_after_tracking() {
+        let history_file = std::env::temp_dir().join(format!(
+            "lotrust_acc_defn_history_{}.npy",
+            std::process::id()
+        ));
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        sim.save_filename = Some(history_file.to_string_lossy().into_owned());
+        sim.track().unwrap();
+
+        let history: Array3<f64> = read_npy(&history_file).unwrap();
+        std::fs::remove_file(&history_file).unwrap();
+        let n_particles = sim.input_beam.pos.shape()[0];
+        assert_eq!(history.shape(), &[sim.elements.len() + 1, n_particles, 3]);
+        let first = history.index_axis(Axis(0), 0);
+        assert_eq!(first.slice(s![.., ..2]), sim.input_beam.pos);
+        let last = history.index_axis(Axis(0), sim.elements.len());
+        assert_eq!(last.slice(s![.., ..2]), sim.output_beam.pos_by_id());
+        let ids = Array1::from_iter((0..n_particles).map(|id| id as f64));
+        assert_eq!(last.column(2), ids);
+    }
+
+    #[test]
+    fn tracking_stops_at_breakpoints_and_the_end() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        sim.breakpoints.push(2);
+        assert_eq!(sim.track().unwrap(), 2);
+        assert_eq!(sim.track().unwrap(), sim.elements.len() - 2);
+        assert!(matches!(sim.track(), Err(LotrError::TrackedToEnd)));
+        assert!(matches!(sim.step(), Err(LotrError::TrackedToEnd)));
+    }
+
+    #[test]
+    fn unknown_character_is_an_error() {
+        let filename = std::env::temp_dir().join(format!(
+            "lotrust_unknown_character_{}.lotr",
+            std::process::id()
+        ));
+        std::fs::write(&filename, "accelerator {\n    drift: 1.0 @\n}\n").unwrap();
+        let loaded = load_lotr_file(&filename.to_string_lossy());
+        std::fs::remove_file(&filename).unwrap();
+        assert!(matches!(loaded, Err(LotrError::Syntax { loc, .. }) if loc.row == 2));
+    }
+
+    #[test]
+    fn history_is_only_kept_for_saving() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        sim.track().unwrap();
+        assert!(sim.history.is_empty());
+        assert_eq!(sim.stats_history.len(), sim.elements.len() + 1);
+        let last = sim.stats_history.last().unwrap();
+        assert_eq!(last.mean_z, sim.output_beam.stats().mean_z);
+    }
+
+    #[test]
+    fn history_of_lost_particle_ends() {
+        let mut sim = load_lotr_file(ACC_TESTFILE).unwrap();
+        sim.elements[0] = make_collimator(
+            "collimator".to_string(),
+            sim.elements[0].length,
+            sim.elements[0].gamma,
+            CollimatorDetails {
+                delta_max: 0f64,
+                ..Default::default()
+            },
         );
-        exit(1);
+        sim.track().unwrap();
+        let lost_id = sim
+            .output_beam
+            .lost
+            .iter()
+            .position(Option::is_some)
+            .unwrap();
+        let lost_id = sim.output_beam.ids[lost_id];
+        let before = sim.beam_entering(0).phase_space_by_id();
+        let after = sim.beam_entering(1).phase_space_by_id();
+        assert!(before[[lost_id, 0]].is_finite());
+        assert!(after[[lost_id, 0]].is_nan() && after[[lost_id, 1]].is_nan());
+        assert_eq!(after[[lost_id, 2]], lost_id as f64);
     }
 }
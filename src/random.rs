@@ -0,0 +1,86 @@
+/// A small, seedable pseudo-random number generator (xorshift64*, seeded through splitmix64).
+/// Good enough for generating beams and stochastic kicks, and reproducible between runs.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    spare_gaussian: Option<f64>,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 0x2545_F491_4F6C_DD1D } else { z },
+            spare_gaussian: None,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniformly distributed in [0, 1)
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Normally distributed with zero mean and unit variance (Box-Muller)
+    pub fn gaussian(&mut self) -> f64 {
+        if let Some(val) = self.spare_gaussian.take() {
+            return val;
+        }
+        let u1 = 1f64 - self.uniform();
+        let u2 = self.uniform();
+        let radius = (-2f64 * u1.ln()).sqrt();
+        let theta = 2f64 * std::f64::consts::PI * u2;
+        self.spare_gaussian = Some(radius * theta.sin());
+        radius * theta.cos()
+    }
+}
+
+/// Default lattice seed, the same as elegant's `random_number_seed`
+pub const DEFAULT_SEED: u64 = 987654321;
+
+/// Seed for the random kicks of the element at `index` in a lattice seeded with `seed`, so that
+/// each element draws its own sequence
+pub fn element_seed(seed: u64, index: usize) -> u64 {
+    Rng::new(seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)).next_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let mut rng1 = Rng::new(42);
+        let mut rng2 = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(rng1.uniform(), rng2.uniform());
+        }
+    }
+
+    #[test]
+    fn elements_draw_their_own_seeds() {
+        assert_ne!(element_seed(DEFAULT_SEED, 0), element_seed(DEFAULT_SEED, 1));
+        assert_ne!(element_seed(1, 3), element_seed(2, 3));
+        assert_eq!(element_seed(7, 3), element_seed(7, 3));
+    }
+
+    #[test]
+    fn gaussian_has_unit_variance() {
+        let mut rng = Rng::new(1);
+        let num = 100000;
+        let vals: Vec<f64> = (0..num).map(|_| rng.gaussian()).collect();
+        let mean = vals.iter().sum::<f64>() / num as f64;
+        let var = vals.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / num as f64;
+        assert!(mean.abs() < 0.01);
+        assert!((var - 1f64).abs() < 0.02);
+    }
+}
@@ -0,0 +1,871 @@
+use crate::beam::{delta_2_gamma, gamma_2_delta, Beam, C};
+use crate::error::{FileLoc, LotrError};
+use crate::namelist::{parse_namelists, Namelist};
+use ndarray::{Array1, Array2};
+use std::fs;
+use std::io::{self, Write};
+
+/// The data types that SDDS columns and parameters may have
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SddsType {
+    Double,
+    Float,
+    Long,
+    ULong,
+    Long64,
+    ULong64,
+    Short,
+    UShort,
+    Char,
+    Str,
+}
+
+impl SddsType {
+    fn from_name(name: &str) -> Option<Self> {
+        let data_type = match name.to_lowercase().as_str() {
+            "double" => SddsType::Double,
+            "float" => SddsType::Float,
+            "long" => SddsType::Long,
+            "ulong" => SddsType::ULong,
+            "long64" => SddsType::Long64,
+            "ulong64" => SddsType::ULong64,
+            "short" => SddsType::Short,
+            "ushort" => SddsType::UShort,
+            "character" => SddsType::Char,
+            "string" => SddsType::Str,
+            _ => return None,
+        };
+        Some(data_type)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SddsType::Double => "double",
+            SddsType::Float => "float",
+            SddsType::Long => "long",
+            SddsType::ULong => "ulong",
+            SddsType::Long64 => "long64",
+            SddsType::ULong64 => "ulong64",
+            SddsType::Short => "short",
+            SddsType::UShort => "ushort",
+            SddsType::Char => "character",
+            SddsType::Str => "string",
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self, SddsType::Char | SddsType::Str)
+    }
+}
+
+/// The description of a column or parameter in the header of an SDDS file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SddsDefinition {
+    pub name: String,
+    pub units: String,
+    pub data_type: SddsType,
+    /// The value of a parameter given in the header rather than on each page
+    pub fixed_value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SddsValue {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SddsColumnData {
+    Numbers(Vec<f64>),
+    Text(Vec<String>),
+}
+
+/// One table of an SDDS file, with a value for each parameter and the rows of each column
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SddsPage {
+    pub parameters: Vec<SddsValue>,
+    pub columns: Vec<SddsColumnData>,
+}
+
+/// The contents of an SDDS (Self Describing Data Sets) file, the format used by elegant
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sdds {
+    pub description: String,
+    pub parameters: Vec<SddsDefinition>,
+    pub columns: Vec<SddsDefinition>,
+    pub pages: Vec<SddsPage>,
+}
+
+impl Sdds {
+    /// The values of the numeric column `name` on the given page
+    pub fn column(&self, page: usize, name: &str) -> Option<&[f64]> {
+        let ind = self.columns.iter().position(|col| col.name == name)?;
+        match self.pages.get(page)?.columns.get(ind)? {
+            SddsColumnData::Numbers(values) => Some(values),
+            SddsColumnData::Text(_) => None,
+        }
+    }
+
+    /// The value of the parameter `name` on the given page
+    pub fn parameter(&self, page: usize, name: &str) -> Option<&SddsValue> {
+        let ind = self.parameters.iter().position(|par| par.name == name)?;
+        self.pages.get(page)?.parameters.get(ind)
+    }
+}
+
+/// How the data of an SDDS file is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SddsMode {
+    Binary,
+    Ascii,
+}
+
+/// How the data after the header is laid out
+#[derive(Debug, Default)]
+struct DataLayout {
+    ascii: bool,
+    no_row_counts: bool,
+    column_major: bool,
+    big_endian: bool,
+}
+
+pub fn read_sdds(filename: &str) -> Result<Sdds, LotrError> {
+    let bytes = fs::read(filename).map_err(|source| LotrError::Io {
+        filename: filename.to_string(),
+        source,
+    })?;
+    parse_sdds(&bytes, filename)
+}
+
+/// Reads an SDDS file, in ASCII or binary, from its bytes
+pub fn parse_sdds(bytes: &[u8], filename: &str) -> Result<Sdds, LotrError> {
+    let mut sdds: Sdds = Default::default();
+    let mut layout: DataLayout = Default::default();
+    let mut loc = FileLoc {
+        filename: filename.to_string(),
+        row: 1,
+        col: 1,
+    };
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| {
+        let start = *pos;
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |len| start + len);
+        *pos = (end + 1).min(bytes.len());
+        String::from_utf8_lossy(&bytes[start..end]).into_owned()
+    };
+
+    if !next_line(&mut pos).starts_with("SDDS") {
+        return Err(LotrError::syntax(&loc, "Not an SDDS file"));
+    }
+    let mut pending = String::new();
+    let mut pending_loc = loc.clone();
+    let mut header_done = false;
+    while !header_done {
+        if pos >= bytes.len() {
+            return Err(LotrError::syntax(&loc, "The SDDS header has no '&data'"));
+        }
+        loc.row += 1;
+        let line = next_line(&mut pos);
+        if let Some(comment) = line.strip_prefix("!#") {
+            layout.big_endian = comment.trim() == "big-endian";
+            continue;
+        }
+        if line.starts_with('!') {
+            continue;
+        }
+        if pending.is_empty() {
+            pending_loc = loc.clone();
+        }
+        pending.push_str(&line);
+        pending.push('\n');
+        if !pending.to_lowercase().contains("&end") {
+            continue;
+        }
+        for namelist in parse_namelists(&pending, &pending_loc)? {
+            if namelist.name == "data" {
+                read_data_namelist(&namelist, &mut layout)?;
+                for _ in 0..namelist.get_f64("additional_header_lines")?.unwrap_or(0f64) as usize {
+                    next_line(&mut pos);
+                }
+                header_done = true;
+            } else {
+                read_header_namelist(&namelist, &mut sdds)?;
+            }
+        }
+        pending.clear();
+    }
+
+    let data = &bytes[pos..];
+    if layout.ascii {
+        read_ascii_pages(data, &layout, &mut sdds, filename)?;
+    } else {
+        read_binary_pages(data, &layout, &mut sdds, filename)?;
+    }
+    Ok(sdds)
+}
+
+fn read_definition(namelist: &Namelist) -> Result<SddsDefinition, LotrError> {
+    let name = namelist.get("name").ok_or_else(|| {
+        LotrError::syntax(&namelist.loc, format!("'&{}' has no name", namelist.name))
+    })?;
+    let type_name = namelist.get("type").unwrap_or_default();
+    let data_type = SddsType::from_name(type_name).ok_or_else(|| {
+        LotrError::syntax(
+            &namelist.loc,
+            format!("Unknown SDDS type '{type_name}' for '{name}'"),
+        )
+    })?;
+    Ok(SddsDefinition {
+        name: name.to_string(),
+        units: namelist.get("units").unwrap_or_default().to_string(),
+        data_type,
+        fixed_value: namelist.get("fixed_value").map(str::to_string),
+    })
+}
+
+fn read_header_namelist(namelist: &Namelist, sdds: &mut Sdds) -> Result<(), LotrError> {
+    match namelist.name.as_str() {
+        "description" => {
+            let text = namelist.get("text").or(namelist.get("contents"));
+            sdds.description = text.unwrap_or_default().to_string();
+        }
+        "column" => sdds.columns.push(read_definition(namelist)?),
+        "parameter" => sdds.parameters.push(read_definition(namelist)?),
+        "associate" => {}
+        other => {
+            return Err(LotrError::syntax(
+                &namelist.loc,
+                format!("Cannot handle '&{other}' in an SDDS header"),
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn read_data_namelist(namelist: &Namelist, layout: &mut DataLayout) -> Result<(), LotrError> {
+    layout.ascii = match namelist.get("mode").unwrap_or("binary") {
+        "ascii" => true,
+        "binary" => false,
+        other => {
+            return Err(LotrError::syntax(
+                &namelist.loc,
+                format!("Unknown SDDS data mode '{other}'"),
+            ))
+        }
+    };
+    layout.no_row_counts = namelist.get_flag("no_row_counts")?;
+    layout.column_major = namelist.get_flag("column_major_order")?;
+    Ok(())
+}
+
+fn parse_value(text: &str, data_type: SddsType) -> Option<SddsValue> {
+    if data_type.is_text() {
+        Some(SddsValue::Text(text.to_string()))
+    } else {
+        text.parse().ok().map(SddsValue::Number)
+    }
+}
+
+fn empty_columns(sdds: &Sdds) -> Vec<SddsColumnData> {
+    sdds.columns
+        .iter()
+        .map(|col| match col.data_type.is_text() {
+            true => SddsColumnData::Text(vec![]),
+            false => SddsColumnData::Numbers(vec![]),
+        })
+        .collect()
+}
+
+fn push_value(column: &mut SddsColumnData, value: SddsValue) {
+    match (column, value) {
+        (SddsColumnData::Numbers(values), SddsValue::Number(val)) => values.push(val),
+        (SddsColumnData::Text(values), SddsValue::Text(val)) => values.push(val),
+        _ => unreachable!("values are parsed with the type of their column"),
+    }
+}
+
+/// The parameters of a page: fixed values from the header, and the rest from `read_next`
+fn read_parameters(
+    sdds: &Sdds,
+    filename: &str,
+    mut read_next: impl FnMut(SddsType) -> Result<SddsValue, LotrError>,
+) -> Result<Vec<SddsValue>, LotrError> {
+    sdds.parameters
+        .iter()
+        .map(|par| match &par.fixed_value {
+            Some(text) => parse_value(text, par.data_type).ok_or_else(|| LotrError::BadData {
+                filename: filename.to_string(),
+                message: format!("Cannot read '{text}' as the value of '{}'", par.name),
+            }),
+            None => read_next(par.data_type),
+        })
+        .collect()
+}
+
+/// Splits a line of ASCII SDDS data into values, keeping quoted strings together
+fn split_ascii(line: &str) -> Vec<String> {
+    let mut values = vec![];
+    let mut chars = line.trim().chars().peekable();
+    while let Some(chr) = chars.next() {
+        if chr.is_whitespace() {
+            continue;
+        }
+        let mut value = String::new();
+        if chr == '"' {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            value.push(chr);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        values.push(value);
+    }
+    values
+}
+
+fn read_ascii_pages(
+    data: &[u8],
+    layout: &DataLayout,
+    sdds: &mut Sdds,
+    filename: &str,
+) -> Result<(), LotrError> {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.starts_with('!'))
+        .peekable();
+    let bad_data = |message: String| LotrError::BadData {
+        filename: filename.to_string(),
+        message,
+    };
+    loop {
+        while lines.next_if(|line| line.trim().is_empty()).is_some() {}
+        if lines.peek().is_none() {
+            return Ok(());
+        }
+        let parameters = read_parameters(sdds, filename, |data_type| {
+            let line = lines
+                .next()
+                .ok_or_else(|| bad_data("The last page is missing parameters".to_string()))?;
+            let line = line.trim();
+            let text = match data_type.is_text() {
+                true => line.trim_matches('"').to_string(),
+                false => line.to_string(),
+            };
+            parse_value(&text, data_type)
+                .ok_or_else(|| bad_data(format!("Cannot read '{line}' as a parameter")))
+        })?;
+
+        let num_cols = sdds.columns.len();
+        let mut values: Vec<String> = vec![];
+        if layout.no_row_counts {
+            while let Some(line) = lines.next_if(|line| !line.trim().is_empty()) {
+                values.extend(split_ascii(line));
+            }
+        } else {
+            let count = match lines.next() {
+                Some(line) => line.trim().parse::<usize>().ok(),
+                None => None,
+            };
+            let count = count.ok_or_else(|| bad_data("Expected a row count".to_string()))?;
+            while values.len() < count * num_cols {
+                let line = lines
+                    .next()
+                    .ok_or_else(|| bad_data(format!("Expected {count} rows")))?;
+                values.extend(split_ascii(line));
+            }
+        }
+        if num_cols > 0 && !values.len().is_multiple_of(num_cols) {
+            return Err(bad_data(format!(
+                "{} values do not fill rows of {num_cols} columns",
+                values.len()
+            )));
+        }
+
+        let mut columns = empty_columns(sdds);
+        for row in values.chunks(num_cols.max(1)) {
+            for ((text, column), defn) in row.iter().zip(&mut columns).zip(&sdds.columns) {
+                let value = parse_value(text, defn.data_type).ok_or_else(|| {
+                    bad_data(format!("Cannot read '{text}' in column '{}'", defn.name))
+                })?;
+                push_value(column, value);
+            }
+        }
+        sdds.pages.push(SddsPage {
+            parameters,
+            columns,
+        });
+    }
+}
+
+/// Reads binary values from the data section of an SDDS file
+struct BinaryReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+    filename: &'a str,
+}
+
+impl BinaryReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], LotrError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| LotrError::BadData {
+                filename: self.filename.to_string(),
+                message: "The binary data ends in the middle of a page".to_string(),
+            })?;
+        self.pos += N;
+        let mut array: [u8; N] = bytes.try_into().unwrap();
+        if self.big_endian {
+            array.reverse();
+        }
+        Ok(array)
+    }
+
+    fn read_i32(&mut self) -> Result<i32, LotrError> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn read(&mut self, data_type: SddsType) -> Result<SddsValue, LotrError> {
+        let value = match data_type {
+            SddsType::Double => f64::from_le_bytes(self.take()?),
+            SddsType::Float => f32::from_le_bytes(self.take()?) as f64,
+            SddsType::Long => self.read_i32()? as f64,
+            SddsType::ULong => u32::from_le_bytes(self.take()?) as f64,
+            SddsType::Long64 => i64::from_le_bytes(self.take()?) as f64,
+            SddsType::ULong64 => u64::from_le_bytes(self.take()?) as f64,
+            SddsType::Short => i16::from_le_bytes(self.take()?) as f64,
+            SddsType::UShort => u16::from_le_bytes(self.take()?) as f64,
+            SddsType::Char => {
+                let [chr] = self.take()?;
+                return Ok(SddsValue::Text((chr as char).to_string()));
+            }
+            SddsType::Str => {
+                let len = self.read_i32()?.max(0) as usize;
+                let bytes =
+                    self.data
+                        .get(self.pos..self.pos + len)
+                        .ok_or_else(|| LotrError::BadData {
+                            filename: self.filename.to_string(),
+                            message: "The binary data ends in the middle of a string".to_string(),
+                        })?;
+                self.pos += len;
+                return Ok(SddsValue::Text(String::from_utf8_lossy(bytes).into_owned()));
+            }
+        };
+        Ok(SddsValue::Number(value))
+    }
+}
+
+fn read_binary_pages(
+    data: &[u8],
+    layout: &DataLayout,
+    sdds: &mut Sdds,
+    filename: &str,
+) -> Result<(), LotrError> {
+    let mut reader = BinaryReader {
+        data,
+        pos: 0,
+        big_endian: layout.big_endian,
+        filename,
+    };
+    while reader.pos < data.len() {
+        let num_rows = reader.read_i32()?.max(0) as usize;
+        let parameters = read_parameters(sdds, filename, |data_type| reader.read(data_type))?;
+        let mut columns = empty_columns(sdds);
+        if layout.column_major {
+            for (column, defn) in columns.iter_mut().zip(&sdds.columns) {
+                for _ in 0..num_rows {
+                    push_value(column, reader.read(defn.data_type)?);
+                }
+            }
+        } else {
+            for _ in 0..num_rows {
+                for (column, defn) in columns.iter_mut().zip(&sdds.columns) {
+                    push_value(column, reader.read(defn.data_type)?);
+                }
+            }
+        }
+        sdds.pages.push(SddsPage {
+            parameters,
+            columns,
+        });
+    }
+    Ok(())
+}
+
+/// Writes `sdds` as a little-endian binary or an ASCII SDDS file
+pub fn write_sdds(sink: &mut impl Write, sdds: &Sdds, mode: SddsMode) -> io::Result<()> {
+    writeln!(sink, "SDDS1")?;
+    if mode == SddsMode::Binary {
+        writeln!(sink, "!# little-endian")?;
+    }
+    if !sdds.description.is_empty() {
+        writeln!(
+            sink,
+            "&description text={}, &end",
+            quoted(&sdds.description)
+        )?;
+    }
+    for (kind, definitions) in [("parameter", &sdds.parameters), ("column", &sdds.columns)] {
+        for defn in definitions {
+            write!(sink, "&{kind} name={}, ", quoted(&defn.name))?;
+            if !defn.units.is_empty() {
+                write!(sink, "units={}, ", quoted(&defn.units))?;
+            }
+            write!(sink, "type={}, ", defn.data_type.name())?;
+            if let Some(value) = &defn.fixed_value {
+                write!(sink, "fixed_value={}, ", quoted(value))?;
+            }
+            writeln!(sink, "&end")?;
+        }
+    }
+    match mode {
+        SddsMode::Binary => writeln!(sink, "&data mode=binary, &end")?,
+        SddsMode::Ascii => writeln!(sink, "&data mode=ascii, &end")?,
+    }
+
+    for page in &sdds.pages {
+        let num_rows = page.columns.first().map_or(0, |column| match column {
+            SddsColumnData::Numbers(values) => values.len(),
+            SddsColumnData::Text(values) => values.len(),
+        });
+        let parameters = sdds.parameters.iter().zip(&page.parameters);
+        let per_page = parameters.filter(|(defn, _)| defn.fixed_value.is_none());
+        match mode {
+            SddsMode::Binary => {
+                sink.write_all(&(num_rows as i32).to_le_bytes())?;
+                for (defn, value) in per_page {
+                    write_binary(sink, value, defn.data_type)?;
+                }
+                for row in 0..num_rows {
+                    for (column, defn) in page.columns.iter().zip(&sdds.columns) {
+                        write_binary(sink, &column_value(column, row), defn.data_type)?;
+                    }
+                }
+            }
+            SddsMode::Ascii => {
+                for (_, value) in per_page {
+                    writeln!(sink, "{}", ascii_value(value))?;
+                }
+                writeln!(sink, "{num_rows}")?;
+                for row in 0..num_rows {
+                    let values: Vec<String> = page
+                        .columns
+                        .iter()
+                        .map(|column| ascii_value(&column_value(column, row)))
+                        .collect();
+                    writeln!(sink, "{}", values.join(" "))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quotes text that would otherwise be split when read back
+fn quoted(text: &str) -> String {
+    if text.is_empty() || text.contains(|c: char| c.is_whitespace() || ",=\"!&".contains(c)) {
+        format!("\"{}\"", text.replace('"', "\\\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn column_value(column: &SddsColumnData, row: usize) -> SddsValue {
+    match column {
+        SddsColumnData::Numbers(values) => SddsValue::Number(values[row]),
+        SddsColumnData::Text(values) => SddsValue::Text(values[row].clone()),
+    }
+}
+
+fn ascii_value(value: &SddsValue) -> String {
+    match value {
+        SddsValue::Number(val) => format!("{val:e}"),
+        SddsValue::Text(text) => quoted(text),
+    }
+}
+
+fn write_binary(sink: &mut impl Write, value: &SddsValue, data_type: SddsType) -> io::Result<()> {
+    let number = match value {
+        SddsValue::Number(val) => *val,
+        SddsValue::Text(text) => {
+            if data_type == SddsType::Char {
+                return sink.write_all(&[text.bytes().next().unwrap_or(b' ')]);
+            }
+            sink.write_all(&(text.len() as i32).to_le_bytes())?;
+            return sink.write_all(text.as_bytes());
+        }
+    };
+    match data_type {
+        SddsType::Double => sink.write_all(&number.to_le_bytes()),
+        SddsType::Float => sink.write_all(&(number as f32).to_le_bytes()),
+        SddsType::Long => sink.write_all(&(number as i32).to_le_bytes()),
+        SddsType::ULong => sink.write_all(&(number as u32).to_le_bytes()),
+        SddsType::Long64 => sink.write_all(&(number as i64).to_le_bytes()),
+        SddsType::ULong64 => sink.write_all(&(number as u64).to_le_bytes()),
+        SddsType::Short => sink.write_all(&(number as i16).to_le_bytes()),
+        SddsType::UShort => sink.write_all(&(number as u16).to_le_bytes()),
+        SddsType::Char | SddsType::Str => unreachable!("text types hold text values"),
+    }
+}
+
+/// Loads the first page of an elegant particle file. See [`beam_from_sdds`].
+pub fn load_sdds_beam(
+    filename: &str,
+    gamma0: Option<f64>,
+    center_arrival_time: bool,
+) -> Result<(Beam, f64), LotrError> {
+    let sdds = read_sdds(filename)?;
+    beam_from_sdds(&sdds, filename, gamma0, center_arrival_time)
+}
+
+/// Makes a beam from the first page of an elegant particle file, converting its arrival times
+/// `t` (s) and momenta `p` (beta gamma) to z and delta. delta is relative to `gamma0`, or to the
+/// mean gamma of the particles if that is not given, and the arrival times are taken relative to
+/// their mean if `center_arrival_time` is set. The beam has the charge given by a `Charge`
+/// parameter and the weights given by a `weight` column, if there are. Returns the beam and its
+/// reference gamma.
+pub fn beam_from_sdds(
+    sdds: &Sdds,
+    filename: &str,
+    gamma0: Option<f64>,
+    center_arrival_time: bool,
+) -> Result<(Beam, f64), LotrError> {
+    let column = |name: &str| {
+        sdds.column(0, name).ok_or_else(|| LotrError::BadData {
+            filename: filename.to_string(),
+            message: format!("The first page has no numeric '{name}' column"),
+        })
+    };
+    let (mut beam, gamma0) =
+        beam_from_t_and_p(column("t")?, column("p")?, gamma0, center_arrival_time);
+    if let Some(SddsValue::Number(charge)) = sdds.parameter(0, "Charge") {
+        beam.charge = *charge;
+    }
+    if let Some(weights) = sdds.column(0, "weight") {
+        beam.weights = Array1::from(weights.to_vec());
+    }
+    Ok((beam, gamma0))
+}
+
+/// A beam from elegant's arrival times `t` (s) and momenta `p` (beta gamma), with delta
+/// relative to `gamma0` or the mean gamma of the particles. Returns the beam and its reference
+/// gamma.
+pub(crate) fn beam_from_t_and_p(
+    t: &[f64],
+    p: &[f64],
+    gamma0: Option<f64>,
+    center_arrival_time: bool,
+) -> (Beam, f64) {
+    let num_particles = t.len();
+    let gamma: Vec<f64> = p.iter().map(|p| (1f64 + p * p).sqrt()).collect();
+    let gamma0 = gamma0.unwrap_or(gamma.iter().sum::<f64>() / num_particles as f64);
+    let t_ref = match center_arrival_time {
+        true => t.iter().sum::<f64>() / num_particles as f64,
+        false => 0f64,
+    };
+
+    let mut pos = Array2::zeros((num_particles, 2));
+    for (ind, mut row) in pos.rows_mut().into_iter().enumerate() {
+        let beta = p[ind] / gamma[ind];
+        row[0] = -beta * C * (t[ind] - t_ref);
+        row[1] = gamma_2_delta(gamma[ind], gamma0);
+    }
+    (Beam::new(pos), gamma0)
+}
+
+/// An elegant particle file of the particles left in `beam`, in order of their ids, with delta
+/// measured from `gamma0`. The transverse coordinates are zero, and the `particleID`s count from
+/// one as elegant's do. A weighted beam also gets a `weight` column, which elegant ignores.
+pub fn beam_to_sdds(beam: &Beam, gamma0: f64) -> Sdds {
+    let live = beam.live_particles();
+    let rows = live.rows_by_id();
+    let gamma: Vec<f64> = rows
+        .iter()
+        .map(|&row| delta_2_gamma(live.pos[[row, 1]], gamma0))
+        .collect();
+    let p: Vec<f64> = gamma.iter().map(|g| (g * g - 1f64).sqrt()).collect();
+    let t: Vec<f64> = rows
+        .iter()
+        .zip(&gamma)
+        .zip(&p)
+        .map(|((&row, g), p)| -live.pos[[row, 0]] / (p / g * C))
+        .collect();
+    let ids: Vec<f64> = rows.iter().map(|&row| (live.ids[row] + 1) as f64).collect();
+    let weights: Vec<f64> = rows.iter().map(|&row| live.weights[row]).collect();
+    let zeros = vec![0f64; rows.len()];
+
+    let definition = |name: &str, units: &str, data_type: SddsType| SddsDefinition {
+        name: name.to_string(),
+        units: units.to_string(),
+        data_type,
+        fixed_value: None,
+    };
+    let mut sdds = Sdds {
+        description: "Beam written by lotrust".to_string(),
+        parameters: vec![
+            definition("pCentral", "m$be$nc", SddsType::Double),
+            definition("Charge", "C", SddsType::Double),
+            definition("Particles", "", SddsType::Long),
+        ],
+        columns: vec![
+            definition("x", "m", SddsType::Double),
+            definition("xp", "", SddsType::Double),
+            definition("y", "m", SddsType::Double),
+            definition("yp", "", SddsType::Double),
+            definition("t", "s", SddsType::Double),
+            definition("p", "m$be$nc", SddsType::Double),
+            definition("particleID", "", SddsType::ULong),
+        ],
+        pages: vec![SddsPage {
+            parameters: vec![
+                SddsValue::Number((gamma0 * gamma0 - 1f64).sqrt()),
+                SddsValue::Number(live.charge),
+                SddsValue::Number(rows.len() as f64),
+            ],
+            columns: vec![
+                SddsColumnData::Numbers(zeros.clone()),
+                SddsColumnData::Numbers(zeros.clone()),
+                SddsColumnData::Numbers(zeros.clone()),
+                SddsColumnData::Numbers(zeros),
+                SddsColumnData::Numbers(t),
+                SddsColumnData::Numbers(p),
+                SddsColumnData::Numbers(ids),
+            ],
+        }],
+    };
+    if weights.iter().any(|&weight| weight != weights[0]) {
+        sdds.columns
+            .push(definition("weight", "", SddsType::Double));
+        sdds.pages[0].columns.push(SddsColumnData::Numbers(weights));
+    }
+    sdds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SDDS_BEAM: &str = "tests/elegant_test_runs/input/test_beam.sdds";
+    const CSV_BEAM: &str = "tests/elegant_test_runs/input/test_beam.csv";
+
+    #[test]
+    fn binary_file_matches_its_csv_export() {
+        let sdds = read_sdds(SDDS_BEAM).unwrap();
+        let names: Vec<&str> = sdds.columns.iter().map(|col| col.name.as_str()).collect();
+        assert_eq!(names, ["x", "xp", "y", "yp", "t", "p"]);
+        assert_eq!(sdds.columns[0].units, "m");
+        assert_eq!(sdds.pages.len(), 1);
+
+        let csv = fs::read_to_string(CSV_BEAM).unwrap();
+        let rows: Vec<Vec<f64>> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').map(|val| val.parse().unwrap()).collect())
+            .collect();
+        let t = sdds.column(0, "t").unwrap();
+        let p = sdds.column(0, "p").unwrap();
+        assert_eq!(t.len(), rows.len());
+        for ((t, p), row) in t.iter().zip(p).zip(&rows) {
+            assert_eq!(*t, row[4] as f32 as f64);
+            assert_eq!(*p, row[5] as f32 as f64);
+        }
+    }
+
+    #[test]
+    fn ascii_pages_are_read() {
+        let contents = b"SDDS1\n&parameter name=Charge, type=double, &end\n\
+            &parameter name=Step, type=long, fixed_value=3, &end\n\
+            &column name=t, units=s, type=double, &end\n&column name=label, type=string, &end\n\
+            &data mode=ascii, &end\n! a comment\n1e-10\n2\n1.5 \"first one\"\n2.5 second\n\n\
+            2e-10\n1\n-1 third\n";
+        let sdds = parse_sdds(contents, "test.sdds").unwrap();
+        assert_eq!(sdds.pages.len(), 2);
+        assert_eq!(sdds.column(0, "t"), Some(&[1.5, 2.5][..]));
+        assert_eq!(
+            sdds.pages[0].columns[1],
+            SddsColumnData::Text(vec!["first one".to_string(), "second".to_string()])
+        );
+        assert_eq!(sdds.parameter(0, "Step"), Some(&SddsValue::Number(3f64)));
+        assert_eq!(sdds.parameter(1, "Charge"), Some(&SddsValue::Number(2e-10)));
+        assert_eq!(sdds.column(1, "t"), Some(&[-1f64][..]));
+    }
+
+    #[test]
+    fn written_files_read_back_the_same() {
+        let sdds = parse_sdds(
+            b"SDDS1\n&description text=\"A, test\", &end\n\
+            &parameter name=Label, type=string, &end\n\
+            &parameter name=Pass, type=long, fixed_value=2, &end\n\
+            &column name=t, units=s, type=double, &end\n&column name=n, type=short, &end\n\
+            &column name=s, type=string, &end\n\
+            &data mode=ascii, &end\n\"first page\"\n2\n1e-10 3 \"a b\"\n-2.5 -4 c\nlast\n0\n",
+            "test.sdds",
+        )
+        .unwrap();
+        for mode in [SddsMode::Binary, SddsMode::Ascii] {
+            let mut written: Vec<u8> = vec![];
+            write_sdds(&mut written, &sdds, mode).unwrap();
+            assert_eq!(parse_sdds(&written, "written.sdds").unwrap(), sdds);
+        }
+    }
+
+    #[test]
+    fn beams_survive_a_round_trip() {
+        let gamma0 = 200f64;
+        let mut beam = Beam::new(ndarray::arr2(&[[1e-3, 0.01], [-2e-3, -0.02], [0f64, 0f64]]));
+        beam.charge = 30e-12;
+        beam.sort_by_z();
+        let mut written: Vec<u8> = vec![];
+        write_sdds(&mut written, &beam_to_sdds(&beam, gamma0), SddsMode::Binary).unwrap();
+        let sdds = parse_sdds(&written, "beam.sdds").unwrap();
+        assert_eq!(sdds.column(0, "particleID"), Some(&[1f64, 2f64, 3f64][..]));
+
+        let (read, read_gamma0) = beam_from_sdds(&sdds, "beam.sdds", Some(gamma0), false).unwrap();
+        assert_eq!(read_gamma0, gamma0);
+        assert_eq!(read.charge, beam.charge);
+        let original = beam.pos_by_id();
+        for (a, b) in read.pos.iter().zip(original.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+        assert_eq!(sdds.column(0, "weight"), None);
+    }
+
+    #[test]
+    fn weights_survive_a_round_trip() {
+        let mut beam = Beam::new(ndarray::arr2(&[[1e-3, 0.01], [-2e-3, -0.02], [0f64, 0f64]]));
+        beam.weights = ndarray::arr1(&[1f64, 2f64, 0.5]);
+        let mut written: Vec<u8> = vec![];
+        write_sdds(&mut written, &beam_to_sdds(&beam, 200f64), SddsMode::Binary).unwrap();
+        let sdds = parse_sdds(&written, "beam.sdds").unwrap();
+        let (read, _) = beam_from_sdds(&sdds, "beam.sdds", Some(200f64), false).unwrap();
+        assert_eq!(read.weights, beam.weights);
+    }
+
+    #[test]
+    fn elegant_beam_becomes_z_and_delta() {
+        let gamma0 = (1f64 + 490.236775979206f64.powi(2)).sqrt();
+        let (beam, beam_gamma0) = load_sdds_beam(SDDS_BEAM, Some(gamma0), false).unwrap();
+        assert_eq!(beam.pos.nrows(), 34);
+        assert_eq!(beam_gamma0, gamma0);
+        let z_max = beam.pos.column(0).fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        assert!((z_max - 5e-3).abs() < 1e-9);
+        let delta_max = beam.pos.column(1).fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        assert!((delta_max - 0.0099796).abs() < 1e-6);
+
+        let (_, mean_gamma) = load_sdds_beam(SDDS_BEAM, None, true).unwrap();
+        assert!((mean_gamma - gamma0).abs() < 1e-3 * gamma0);
+    }
+}
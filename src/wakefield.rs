@@ -0,0 +1,190 @@
+use crate::beam::C;
+use crate::error::LotrError;
+use crate::sdds::read_sdds;
+use ndarray::ArrayView1;
+use std::f64::consts::PI;
+
+/// Impedance of free space (Ohm)
+pub(crate) const Z0: f64 = 376.730313668;
+/// Number of slices used when convolving the wake with the bunch profile
+pub const WAKE_BINS: usize = 100;
+
+/// Longitudinal wake function of an accelerating structure, per unit length of structure.
+/// `s` is the distance (m) of the witness particle behind the source particle.
+#[derive(Debug, Clone)]
+pub enum Wake {
+    /// Tabulated W(s) in V/C/m. Zero outside of the table.
+    Table { s: Vec<f64>, w: Vec<f64> },
+    /// K. Bane's fit to the short-range wake of a periodic disk-loaded (SLAC-type) structure.
+    SlacBane {
+        iris_radius: f64,
+        gap: f64,
+        period: f64,
+    },
+}
+
+impl Wake {
+    pub fn value(&self, s: f64) -> f64 {
+        match self {
+            Wake::Table { s: s_tab, w } => {
+                if s_tab.is_empty() || s < s_tab[0] || s > s_tab[s_tab.len() - 1] {
+                    return 0f64;
+                }
+                let ind = s_tab.partition_point(|&x| x < s);
+                if ind == 0 {
+                    return w[0];
+                }
+                let frac = (s - s_tab[ind - 1]) / (s_tab[ind] - s_tab[ind - 1]);
+                w[ind - 1] + frac * (w[ind] - w[ind - 1])
+            }
+            Wake::SlacBane {
+                iris_radius,
+                gap,
+                period,
+            } => {
+                let s0 = 0.41 * iris_radius.powf(1.8) * gap.powf(1.6) / period.powf(2.4);
+                (Z0 * C / (PI * iris_radius.powi(2))) * (-(s / s0).sqrt()).exp()
+            }
+        }
+    }
+
+    /// Energy change (eV) of each particle at position `z` caused by a bunch of total `charge`
+    /// (C), shared according to `weights`, passing through `length` metres of structure.
+    /// Particles with larger `z` are ahead.
+    pub fn energy_kicks(
+        &self,
+        z: ArrayView1<f64>,
+        weights: ArrayView1<f64>,
+        charge: f64,
+        length: f64,
+    ) -> Vec<f64> {
+        let num_particles = z.len();
+        let total_weight = weights.sum();
+        if num_particles == 0 || charge == 0f64 || total_weight == 0f64 {
+            return vec![0f64; num_particles];
+        }
+        let z_min = z.fold(f64::INFINITY, |a, &b| a.min(b));
+        let z_max = z.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let bin_width = (z_max - z_min) / WAKE_BINS as f64;
+        let bin_of = |z: f64| {
+            if bin_width == 0f64 {
+                0
+            } else {
+                (((z - z_min) / bin_width) as usize).min(WAKE_BINS - 1)
+            }
+        };
+
+        let mut fraction = vec![0f64; WAKE_BINS];
+        for (&z_val, &weight) in z.iter().zip(weights) {
+            fraction[bin_of(z_val)] += weight / total_weight;
+        }
+
+        // The fundamental theorem of beam loading: a particle sees half of its own wake.
+        let mut voltage = vec![0f64; WAKE_BINS];
+        for (witness, volt) in voltage.iter_mut().enumerate() {
+            *volt = 0.5 * fraction[witness] * self.value(0f64);
+            for (source, frac) in fraction.iter().enumerate().skip(witness + 1) {
+                *volt += frac * self.value((source - witness) as f64 * bin_width);
+            }
+        }
+
+        z.iter()
+            .map(|&z_val| -charge * length * voltage[bin_of(z_val)])
+            .collect()
+    }
+}
+
+/// Reads a wake table from the first page of an SDDS file, as given by the `ZWAKEFILE` of an
+/// elegant `RFCW`. `t_column` is the time behind the source (s) and `w_column` the wake (V/C/m).
+/// Column names are matched regardless of case, since elegant lattices are read in lowercase.
+pub fn load_wake_table(filename: &str, t_column: &str, w_column: &str) -> Result<Wake, LotrError> {
+    let sdds = read_sdds(filename)?;
+    let column = |name: &str| {
+        sdds.columns
+            .iter()
+            .find(|col| col.name.eq_ignore_ascii_case(name))
+            .and_then(|col| sdds.column(0, &col.name))
+            .ok_or_else(|| LotrError::BadData {
+                filename: filename.to_string(),
+                message: format!("The first page has no numeric '{name}' column"),
+            })
+    };
+    let mut rows: Vec<(f64, f64)> = column(t_column)?
+        .iter()
+        .zip(column(w_column)?)
+        .map(|(t, w)| (t * C, *w))
+        .collect();
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    Ok(Wake::Table {
+        s: rows.iter().map(|row| row.0).collect(),
+        w: rows.iter().map(|row| row.1).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_ulps_eq;
+    use ndarray::Array1;
+
+    #[test]
+    fn table_wake_interpolates_linearly() {
+        let wake = Wake::Table {
+            s: vec![0f64, 1f64, 2f64],
+            w: vec![10f64, 20f64, 0f64],
+        };
+        assert_ulps_eq!(wake.value(0.5), 15f64);
+        assert_ulps_eq!(wake.value(1.5), 10f64);
+        assert_eq!(wake.value(3f64), 0f64);
+    }
+
+    #[test]
+    fn table_is_read_from_sdds() {
+        let table = load_wake_table("tests/input/wakefieldlong_interp.sdds", "t", "w").unwrap();
+        let wake = Wake::SlacBane {
+            iris_radius: 11.6e-3,
+            gap: 29.2e-3,
+            period: 35e-3,
+        };
+        // The table is tabulated every 0.3 ps
+        for s in [0f64, 30e-12 * C, 90e-13 * C] {
+            assert!((table.value(s) - wake.value(s)).abs() < 1e-9 * wake.value(s));
+        }
+        assert!(matches!(
+            load_wake_table("tests/input/wakefieldlong_interp.sdds", "t", "wx"),
+            Err(LotrError::BadData { .. })
+        ));
+        assert!(matches!(
+            load_wake_table("tests/input/no_such_wake.sdds", "t", "w"),
+            Err(LotrError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn zero_charge_gives_no_kick() {
+        let wake = Wake::SlacBane {
+            iris_radius: 11.6e-3,
+            gap: 29.2e-3,
+            period: 35e-3,
+        };
+        let z = Array1::linspace(-1e-3, 1e-3, 11);
+        let weights = Array1::ones(z.len());
+        let kicks = wake.energy_kicks(z.view(), weights.view(), 0f64, 3f64);
+        assert!(kicks.iter().all(|&kick| kick == 0f64));
+    }
+
+    #[test]
+    fn tail_loses_more_energy_than_head() {
+        let wake = Wake::SlacBane {
+            iris_radius: 11.6e-3,
+            gap: 29.2e-3,
+            period: 35e-3,
+        };
+        let z = Array1::linspace(-1e-3, 1e-3, 11);
+        let weights = Array1::ones(z.len());
+        let kicks = wake.energy_kicks(z.view(), weights.view(), 100e-12, 3f64);
+        assert!(kicks[0] < kicks[10]);
+        assert!(kicks.iter().all(|&kick| kick < 0f64));
+    }
+}